@@ -0,0 +1,161 @@
+use crate::casbin_dao::AqlExecutor;
+use crate::ArangorsAdapterError;
+use arangors::AqlQuery;
+use casbin::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::{Arc, PoisonError, RwLock};
+
+pub(crate) type SharedActor = Arc<RwLock<Option<Actor>>>;
+
+/// The change an [`AuditEntry`] records, named after the adapter method.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    AddPolicy,
+    AddPolicies,
+    RemovePolicy,
+    RemovePolicies,
+    RemoveFilteredPolicy,
+    RemoveMatchingPolicy,
+    SavePolicy,
+    ClearPolicy,
+    RollbackPolicy,
+}
+
+/// A change recorded in the audit collection: one entry per rule added,
+/// removed or saved, or a single one without rule when the whole policy was
+/// cleared.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub operation: AuditOperation,
+    pub ptype: Option<String>,
+    pub rule: Vec<String>,
+    pub actor: Option<String>,
+    pub reason: Option<String>,
+    /// When the change was written, in ISO 8601 from the server's clock.
+    pub timestamp: String,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Actor {
+    name: String,
+    reason: Option<String>,
+}
+
+/// Tells the adapter who makes the next changes and why, for the entries of
+/// the audit collection.
+///
+/// Like a [`crate::TransactionHandle`], the handle shares its state with the
+/// adapter it was obtained from and keeps working after the adapter was
+/// moved into an `Enforcer`. The actor stays set until changed or cleared.
+#[derive(Clone)]
+pub struct AuditHandle {
+    actor: SharedActor,
+}
+
+impl AuditHandle {
+    pub(crate) fn new(actor: SharedActor) -> Self {
+        Self { actor }
+    }
+
+    pub fn set_actor(&self, actor: &str, reason: Option<&str>) {
+        *self.actor.write().unwrap_or_else(PoisonError::into_inner) = Some(Actor {
+            name: actor.to_owned(),
+            reason: reason.map(str::to_owned),
+        });
+    }
+
+    pub fn clear_actor(&self) {
+        *self.actor.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+/// Writes an entry per rule, or a single one without rule if there are none.
+pub(crate) async fn record<E: AqlExecutor>(
+    e: &E,
+    collection: &str,
+    operation: AuditOperation,
+    actor: &SharedActor,
+    rules: &[(String, Vec<String>)],
+) -> Result<()> {
+    let actor = actor.read().unwrap_or_else(PoisonError::into_inner).clone();
+    let (name, reason) = match actor {
+        Some(actor) => (Some(actor.name), actor.reason),
+        None => (None, None),
+    };
+
+    let entry = |ptype: Option<&str>, rule: &[String]| {
+        json!({
+            "operation": operation,
+            "ptype": ptype,
+            "rule": rule,
+            "actor": name,
+            "reason": reason,
+        })
+    };
+    let entries: Vec<Value> = match rules {
+        [] => vec![entry(None, &[])],
+        rules => rules
+            .iter()
+            .map(|(ptype, rule)| entry(Some(ptype), rule))
+            .collect(),
+    };
+
+    let aql = AqlQuery::builder()
+        .query(
+            r#"LET timestamp = DATE_ISO8601(DATE_NOW())
+FOR entry IN @entries
+    INSERT MERGE(entry, {timestamp: timestamp}) INTO @@collection"#,
+        )
+        .bind_var("entries", entries)
+        .bind_var("@collection", collection)
+        .build();
+
+    let _: Vec<Value> = e.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+    Ok(())
+}
+
+/// The entries of the rule, oldest first.
+pub(crate) async fn rule_history<E: AqlExecutor>(
+    e: &E,
+    collection: &str,
+    ptype: &str,
+    rule: &[String],
+) -> Result<Vec<AuditEntry>> {
+    // `IN` can use the array index on `rule[*]`
+    let aql = AqlQuery::builder()
+        .query(
+            r#"FOR a IN @@collection
+    FILTER @rule[0] IN a.rule[*] AND a.ptype == @ptype AND a.rule == @rule
+    SORT a.timestamp
+    RETURN UNSET(a, "_id", "_key", "_rev")"#,
+        )
+        .bind_var("@collection", collection)
+        .bind_var("ptype", ptype)
+        .bind_var("rule", rule.to_vec())
+        .build();
+
+    Ok(e.run(aql).await.map_err(ArangorsAdapterError::from)?)
+}
+
+/// The entries of the rules whose first value is the subject, oldest first.
+pub(crate) async fn subject_history<E: AqlExecutor>(
+    e: &E,
+    collection: &str,
+    subject: &str,
+) -> Result<Vec<AuditEntry>> {
+    let aql = AqlQuery::builder()
+        .query(
+            r#"FOR a IN @@collection
+    FILTER @subject IN a.rule[*] AND a.rule[0] == @subject
+    SORT a.timestamp
+    RETURN UNSET(a, "_id", "_key", "_rev")"#,
+        )
+        .bind_var("@collection", collection)
+        .bind_var("subject", subject)
+        .build();
+
+    Ok(e.run(aql).await.map_err(ArangorsAdapterError::from)?)
+}
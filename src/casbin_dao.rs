@@ -1,248 +1,1180 @@
-use crate::CasbinRule;
+use crate::filter::{FieldMatcher, FilterGroup};
+use crate::graph::Vertex;
+use crate::layout::{Layout, Shape};
+use crate::validity::validity_filter;
+use crate::{ArangorsAdapterError, CasbinRule};
+use arangors::transaction::{Transaction, TransactionCollections, TransactionSettings};
 use arangors::uclient::ClientExt;
-use arangors::{AqlQuery, Database};
+use arangors::{AqlQuery, ClientError, Cursor, Database};
 use async_trait::async_trait;
-use casbin::{error::AdapterError, Result};
+use casbin::Result;
+use futures_util::{stream, Stream};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
 
 #[async_trait]
 pub(crate) trait CasbinDao {
-    async fn save_policy(&self, rules: Vec<CasbinRule>) -> Result<()>;
-    async fn clear_policy(&self) -> Result<()>;
-    async fn load_policy(&self) -> Result<Vec<CasbinRule>>;
-    async fn add_policy(&self, rule: CasbinRule) -> Result<bool>;
-    async fn add_policies(&self, rules: Vec<CasbinRule>) -> Result<bool>;
-    async fn remove_policy(&self, pt: &str, rule: Vec<String>) -> Result<bool>;
-    async fn remove_policies(&self, pt: &str, rules: Vec<Vec<String>>) -> Result<bool>;
+    async fn save_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+    ) -> Result<()>;
+    async fn clear_policy(&self, collection: &str) -> Result<()>;
+    async fn load_policy(&self, collection: &str, layout: &Layout) -> Result<Vec<CasbinRule>>;
+    async fn load_filtered_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        filters: &[(String, Vec<String>)],
+    ) -> Result<Vec<CasbinRule>>;
+    async fn load_grouped_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        groups: &[&FilterGroup],
+    ) -> Result<Vec<CasbinRule>>;
+    async fn load_windowed_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+    ) -> Result<Vec<CasbinRule>>;
+    async fn remove_expired_policy(&self, collection: &str) -> Result<()>;
+    async fn add_policy(&self, collection: &str, layout: &Layout, rule: CasbinRule)
+        -> Result<bool>;
+    async fn add_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+    ) -> Result<bool>;
+    async fn upsert_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+        by_key: bool,
+    ) -> Result<Vec<CasbinRule>>;
+    async fn remove_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        rule: Vec<String>,
+    ) -> Result<bool>;
+    async fn remove_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        rules: Vec<Vec<String>>,
+    ) -> Result<Option<Vec<CasbinRule>>>;
+    async fn remove_policies_by_key(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        keys: Vec<String>,
+    ) -> Result<Option<Vec<CasbinRule>>>;
+    async fn upsert_vertices(&self, collection: &str, vertices: Vec<Vertex>) -> Result<()>;
+    async fn remove_orphan_vertices(
+        &self,
+        vertices: &str,
+        edges: &str,
+        layout: &Layout,
+        keys: Vec<String>,
+    ) -> Result<()>;
     async fn remove_filtered_policy(
         &self,
+        collection: &str,
+        layout: &Layout,
         pt: &str,
         field_index: usize,
         field_values: Vec<String>,
-    ) -> Result<bool>;
+    ) -> Result<Vec<CasbinRule>>;
+    async fn remove_matching_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        field_index: usize,
+        matchers: &[FieldMatcher],
+        dry_run: bool,
+    ) -> Result<Vec<CasbinRule>>;
 }
 
+/// Runs AQL either directly against the database or inside a stream
+/// transaction.
 #[async_trait]
-impl<T: ClientExt + Send> CasbinDao for Database<T> {
-    async fn save_policy(&self, rules: Vec<CasbinRule>) -> Result<()> {
-        let json = serde_json::value::to_value(&rules).unwrap();
+pub(crate) trait AqlExecutor: Sync {
+    type Client: ClientExt + Send;
+
+    async fn run<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + Send;
 
+    /// Runs the query and returns the first batch of its cursor.
+    async fn run_batch<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send;
+
+    /// Fetches the next batch of the cursor `id`.
+    async fn next_batch<R>(&self, id: &str) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send;
+
+    /// Begins a stream transaction writing to `collections`, or returns `None`
+    /// when the executor already runs inside one.
+    async fn begin(
+        &self,
+        collections: Vec<String>,
+    ) -> std::result::Result<Option<Transaction<Self::Client>>, ClientError>;
+}
+
+#[async_trait]
+impl<T: ClientExt + Send> AqlExecutor for Database<T> {
+    type Client = T;
+
+    async fn run<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_query(aql).await
+    }
+
+    async fn run_batch<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_query_batch(aql).await
+    }
+
+    async fn next_batch<R>(&self, id: &str) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_next_batch(id).await
+    }
+
+    async fn begin(
+        &self,
+        collections: Vec<String>,
+    ) -> std::result::Result<Option<Transaction<T>>, ClientError> {
+        let tx = self
+            .begin_transaction(
+                TransactionSettings::builder()
+                    .collections(TransactionCollections::builder().write(collections).build())
+                    .build(),
+            )
+            .await?;
+
+        Ok(Some(tx))
+    }
+}
+
+#[async_trait]
+impl<T: ClientExt + Send> AqlExecutor for Transaction<T> {
+    type Client = T;
+
+    async fn run<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_query(aql).await
+    }
+
+    async fn run_batch<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_query_batch(aql).await
+    }
+
+    async fn next_batch<R>(&self, id: &str) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        self.aql_next_batch(id).await
+    }
+
+    async fn begin(
+        &self,
+        _collections: Vec<String>,
+    ) -> std::result::Result<Option<Transaction<T>>, ClientError> {
+        Ok(None)
+    }
+}
+
+/// What the adapter talks to: the database, or a transaction, either the
+/// caller-controlled one or one begun for a single change.
+pub(crate) enum Executor<'a, T: ClientExt> {
+    Database(&'a Database<T>),
+    Transaction(Arc<Transaction<T>>),
+}
+
+impl<T: ClientExt> Clone for Executor<'_, T> {
+    fn clone(&self) -> Self {
+        match self {
+            Executor::Database(db) => Executor::Database(db),
+            Executor::Transaction(tx) => Executor::Transaction(tx.clone()),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: ClientExt + Send> AqlExecutor for Executor<'_, T> {
+    type Client = T;
+
+    async fn run<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Executor::Database(db) => db.run(aql).await,
+            Executor::Transaction(tx) => tx.run(aql).await,
+        }
+    }
+
+    async fn run_batch<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Executor::Database(db) => db.run_batch(aql).await,
+            Executor::Transaction(tx) => tx.run_batch(aql).await,
+        }
+    }
+
+    async fn next_batch<R>(&self, id: &str) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Executor::Database(db) => db.next_batch(id).await,
+            Executor::Transaction(tx) => tx.next_batch(id).await,
+        }
+    }
+
+    async fn begin(
+        &self,
+        collections: Vec<String>,
+    ) -> std::result::Result<Option<Transaction<T>>, ClientError> {
+        match self {
+            Executor::Database(db) => db.begin(collections).await,
+            Executor::Transaction(tx) => tx.begin(collections).await,
+        }
+    }
+}
+
+#[async_trait]
+impl<E: AqlExecutor> CasbinDao for E {
+    async fn save_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+    ) -> Result<()> {
+        let json = layout
+            .encode_all(&rules)
+            .map_err(ArangorsAdapterError::from)?;
+
+        // readers keep seeing the previous policy until the commit
+        in_transaction(self, vec![collection.to_owned()], |e| async move {
+            replace_policy(&e, collection, &json).await
+        })
+        .await
+    }
+
+    async fn clear_policy(&self, collection: &str) -> Result<()> {
         let aql = AqlQuery::builder()
-            .query("FOR r IN @rules INSERT r IN casbin")
-            .bind_var("rules", json)
+            .query("FOR r IN @@collection REMOVE r IN @@collection")
+            .bind_var("@collection", collection)
             .build();
 
-        let _: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+        let _: Vec<serde_json::value::Value> =
+            self.run(aql).await.map_err(ArangorsAdapterError::from)?;
 
         Ok(())
     }
 
-    async fn clear_policy(&self) -> Result<()> {
+    async fn load_policy(&self, collection: &str, layout: &Layout) -> Result<Vec<CasbinRule>> {
         let aql = AqlQuery::builder()
-            .query("FOR r IN casbin REMOVE r IN casbin")
+            .query("FOR r IN @@collection RETURN r")
+            .bind_var("@collection", collection)
             .build();
 
-        let _: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+        let documents: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
 
-        Ok(())
+        from_documents(layout, documents)
     }
 
-    async fn load_policy(&self) -> Result<Vec<CasbinRule>> {
+    async fn load_filtered_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        filters: &[(String, Vec<String>)],
+    ) -> Result<Vec<CasbinRule>> {
+        if filters.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (query, vars) = filtered_policy_query(layout, filters);
+        let mut bind_vars: HashMap<&str, Value> =
+            vars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+        bind_vars.insert("@collection", Value::from(collection));
+
         let aql = AqlQuery::builder()
-            .query("FOR r IN casbin RETURN r")
+            .query(&query)
+            .bind_vars(bind_vars)
             .build();
 
-        let rules: Vec<CasbinRule> = self
-            .aql_query(aql)
+        let documents: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        from_documents(layout, documents)
+    }
+
+    async fn load_grouped_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        groups: &[&FilterGroup],
+    ) -> Result<Vec<CasbinRule>> {
+        if groups.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let (query, vars) = group_policy_query(layout, groups);
+        let documents = run_documents(self, collection, &query, &vars)
             .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+            .map_err(casbin::Error::from)?;
+
+        from_documents(layout, documents)
+    }
+
+    async fn load_windowed_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+    ) -> Result<Vec<CasbinRule>> {
+        let aql = AqlQuery::builder()
+            .query(
+                r#"FOR r IN @@collection
+    FILTER r.valid_from != null OR r.valid_until != null
+    RETURN r"#,
+            )
+            .bind_var("@collection", collection)
+            .build();
+
+        let documents: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        from_documents(layout, documents)
+    }
 
-        Ok(rules)
+    async fn remove_expired_policy(&self, collection: &str) -> Result<()> {
+        let aql = AqlQuery::builder()
+            .query(
+                r#"FOR r IN @@collection
+    FILTER r.valid_until != null AND r.valid_until * 1000 <= DATE_NOW()
+    REMOVE r IN @@collection"#,
+            )
+            .bind_var("@collection", collection)
+            .build();
+
+        let _: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        Ok(())
     }
 
-    async fn add_policy(&self, rule: CasbinRule) -> Result<bool> {
-        let json = serde_json::value::to_value(&rule).unwrap();
+    async fn add_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rule: CasbinRule,
+    ) -> Result<bool> {
+        let json = layout.encode(&rule).map_err(ArangorsAdapterError::from)?;
 
         let aql = AqlQuery::builder()
-            .query("INSERT @rule IN casbin")
+            .query("INSERT @rule IN @@collection")
             .bind_var("rule", json)
+            .bind_var("@collection", collection)
             .build();
 
-        let _: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+        let _: Vec<serde_json::value::Value> =
+            self.run(aql).await.map_err(ArangorsAdapterError::from)?;
 
         Ok(true)
     }
 
-    async fn add_policies(&self, rules: Vec<CasbinRule>) -> Result<bool> {
-        let json = serde_json::value::to_value(&rules).unwrap();
+    async fn add_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+    ) -> Result<bool> {
+        let json = layout
+            .encode_all(&rules)
+            .map_err(ArangorsAdapterError::from)?;
+
+        in_transaction(self, vec![collection.to_owned()], |e| async move {
+            insert_rules(&e, collection, &json).await
+        })
+        .await?;
+
+        Ok(true)
+    }
+
+    async fn upsert_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        rules: Vec<CasbinRule>,
+        by_key: bool,
+    ) -> Result<Vec<CasbinRule>> {
+        let json = layout
+            .encode_all(&rules)
+            .map_err(ArangorsAdapterError::from)?;
+        // the adapter upserts rules of a single ptype at a time
+        let ptype = rules.first().map(|r| r.ptype.clone()).unwrap_or_default();
+
+        let inserted = in_transaction(self, vec![collection.to_owned()], |e| async move {
+            upsert_rules(&e, collection, layout, &ptype, &json, by_key).await
+        })
+        .await?;
+
+        Ok(rules
+            .into_iter()
+            .zip(inserted)
+            .filter_map(|(rule, inserted)| Some(rule).filter(|_| inserted))
+            .collect())
+    }
+
+    async fn remove_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        rule: Vec<String>,
+    ) -> Result<bool> {
+        remove_rule(self, collection, layout, pt, rule)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn remove_policies(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        rules: Vec<Vec<String>>,
+    ) -> Result<Option<Vec<CasbinRule>>> {
+        if rules.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let (query, vars) = batch_removal_query(layout, pt, rules);
+        let removed = in_transaction(self, vec![collection.to_owned()], |e| async move {
+            run_documents(&e, collection, &query, &vars).await
+        })
+        .await?;
+        if removed.is_empty() {
+            return Ok(None);
+        }
+
+        from_documents(layout, removed).map(Some)
+    }
+
+    async fn remove_policies_by_key(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        keys: Vec<String>,
+    ) -> Result<Option<Vec<CasbinRule>>> {
+        if keys.is_empty() {
+            return Ok(Some(vec![]));
+        }
+
+        let vars = [("keys".to_owned(), Value::from(keys))];
+        let removed = in_transaction(self, vec![collection.to_owned()], |e| async move {
+            run_documents(&e, collection, KEY_REMOVAL_QUERY, &vars).await
+        })
+        .await?;
+        if removed.is_empty() {
+            return Ok(None);
+        }
+
+        from_documents(layout, removed).map(Some)
+    }
+
+    async fn upsert_vertices(&self, collection: &str, vertices: Vec<Vertex>) -> Result<()> {
+        let json = serde_json::value::to_value(&vertices).map_err(ArangorsAdapterError::from)?;
 
         let aql = AqlQuery::builder()
-            .query("FOR r IN @rules INSERT r IN casbin")
-            .bind_var("rules", json)
+            .query(
+                r#"FOR v IN @vertices
+    UPSERT { _key: v._key }
+    INSERT v
+    UPDATE {}
+    IN @@collection"#,
+            )
+            .bind_var("vertices", json)
+            .bind_var("@collection", collection)
             .build();
 
-        let _: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+        let _: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
 
-        Ok(true)
+        Ok(())
     }
 
-    async fn remove_policy(&self, pt: &str, rule: Vec<String>) -> Result<bool> {
-        let rule = normalize_casbin_rule(rule, 0);
+    async fn remove_orphan_vertices(
+        &self,
+        vertices: &str,
+        edges: &str,
+        layout: &Layout,
+        keys: Vec<String>,
+    ) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
 
+        let query = orphan_removal_query(layout);
+        let aql = AqlQuery::builder()
+            .query(&query)
+            .bind_var("keys", keys)
+            .bind_var("@vertices", vertices)
+            .bind_var("@edges", edges)
+            .build();
+
+        let _: Vec<Value> = self.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        Ok(())
+    }
+
+    async fn remove_filtered_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        field_index: usize,
+        field_values: Vec<String>,
+    ) -> Result<Vec<CasbinRule>> {
+        let (query, vars) = filtered_removal_query(layout, pt, field_index, &field_values);
+
+        let removed = in_transaction(self, vec![collection.to_owned()], |e| async move {
+            run_documents(&e, collection, &query, &vars).await
+        })
+        .await?;
+
+        from_documents(layout, removed)
+    }
+
+    async fn remove_matching_policy(
+        &self,
+        collection: &str,
+        layout: &Layout,
+        pt: &str,
+        field_index: usize,
+        matchers: &[FieldMatcher],
+        dry_run: bool,
+    ) -> Result<Vec<CasbinRule>> {
+        let (query, vars) = matching_policy_query(layout, pt, field_index, matchers, dry_run);
+
+        if dry_run {
+            let documents = run_documents(self, collection, &query, &vars)
+                .await
+                .map_err(casbin::Error::from)?;
+            return from_documents(layout, documents);
+        }
+
+        let removed = in_transaction(self, vec![collection.to_owned()], |e| async move {
+            run_documents(&e, collection, &query, &vars).await
+        })
+        .await?;
+
+        from_documents(layout, removed)
+    }
+}
+
+/// Where a cursor read by `stream_policy` stands.
+enum Batch {
+    First,
+    Next(String),
+    Done,
+}
+
+/// Streams the rules of the collection in batches of `batch_size` read from
+/// a cursor, so that a large policy is never held in memory at once.
+pub(crate) fn stream_policy<'a, E: AqlExecutor + Send + 'a>(
+    e: E,
+    collection: String,
+    layout: &'a Layout,
+    batch_size: u32,
+) -> impl Stream<Item = Result<Vec<CasbinRule>>> + Send + 'a {
+    stream::try_unfold((e, Batch::First), move |(e, batch)| {
+        let collection = collection.clone();
+
+        async move {
+            let cursor: Cursor<Value> = match batch {
+                Batch::First => {
+                    let query = format!(
+                        "FOR r IN @@collection FILTER {} RETURN r",
+                        validity_filter("r")
+                    );
+                    let aql = AqlQuery::builder()
+                        .query(&query)
+                        .bind_var("@collection", collection.as_str())
+                        .batch_size(batch_size.max(1))
+                        .build();
+
+                    e.run_batch(aql).await
+                }
+                Batch::Next(id) => e.next_batch(&id).await,
+                Batch::Done => return Ok(None),
+            }
+            .map_err(ArangorsAdapterError::from)?;
+
+            let next = match cursor.id {
+                Some(id) if cursor.more => Batch::Next(id),
+                _ => Batch::Done,
+            };
+
+            Ok(Some((from_documents(layout, cursor.result)?, (e, next))))
+        }
+    })
+}
+
+/// Runs the change in a transaction writing to `collections` begun on `e`,
+/// committed if the change succeeds and aborted otherwise. When `e` already
+/// runs inside a transaction, the change runs on `e` directly.
+pub(crate) async fn in_transaction<'a, E, R, Err, F, Fut>(
+    e: &'a E,
+    collections: Vec<String>,
+    change: F,
+) -> Result<R>
+where
+    E: AqlExecutor,
+    Err: Into<casbin::Error>,
+    F: FnOnce(Scope<'a, E>) -> Fut,
+    Fut: Future<Output = std::result::Result<R, Err>>,
+{
+    let tx = match e
+        .begin(collections)
+        .await
+        .map_err(ArangorsAdapterError::from)?
+    {
+        Some(tx) => Arc::new(tx),
+        None => return change(Scope::Outer(e)).await.map_err(Into::into),
+    };
+
+    match change(Scope::Transaction(tx.clone())).await {
+        Ok(r) => {
+            tx.commit().await.map_err(ArangorsAdapterError::from)?;
+            Ok(r)
+        }
+        Err(e) => {
+            let _ = tx.abort().await;
+            Err(e.into())
+        }
+    }
+}
+
+/// What a change given to `in_transaction` runs on: the executor it was
+/// called with, or the transaction begun for it.
+pub(crate) enum Scope<'a, E: AqlExecutor> {
+    Outer(&'a E),
+    Transaction(Arc<Transaction<E::Client>>),
+}
+
+#[async_trait]
+impl<E: AqlExecutor> AqlExecutor for Scope<'_, E> {
+    type Client = E::Client;
+
+    async fn run<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Vec<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Scope::Outer(e) => e.run(aql).await,
+            Scope::Transaction(tx) => tx.run(aql).await,
+        }
+    }
+
+    async fn run_batch<R>(&self, aql: AqlQuery<'_>) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Scope::Outer(e) => e.run_batch(aql).await,
+            Scope::Transaction(tx) => tx.run_batch(aql).await,
+        }
+    }
+
+    async fn next_batch<R>(&self, id: &str) -> std::result::Result<Cursor<R>, ClientError>
+    where
+        R: DeserializeOwned + Send,
+    {
+        match self {
+            Scope::Outer(e) => e.next_batch(id).await,
+            Scope::Transaction(tx) => tx.next_batch(id).await,
+        }
+    }
+
+    async fn begin(
+        &self,
+        collections: Vec<String>,
+    ) -> std::result::Result<Option<Transaction<E::Client>>, ClientError> {
+        match self {
+            Scope::Outer(e) => e.begin(collections).await,
+            Scope::Transaction(tx) => tx.begin(collections).await,
+        }
+    }
+}
+
+async fn replace_policy<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    rules: &Value,
+) -> std::result::Result<(), ArangorsAdapterError> {
+    let aql = AqlQuery::builder()
+        .query("FOR r IN @@collection REMOVE r IN @@collection")
+        .bind_var("@collection", collection)
+        .build();
+
+    let _: Vec<Value> = e.run(aql).await?;
+
+    insert_rules(e, collection, rules).await
+}
+
+async fn insert_rules<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    rules: &Value,
+) -> std::result::Result<(), ArangorsAdapterError> {
+    let aql = AqlQuery::builder()
+        .query("FOR r IN @rules INSERT r IN @@collection")
+        .bind_var("rules", rules.clone())
+        .bind_var("@collection", collection)
+        .build();
+
+    let _: Vec<Value> = e.run(aql).await?;
+
+    Ok(())
+}
+
+/// Inserts the rules that are not stored yet and returns whether each one
+/// was. With `by_key` the rules are matched on their `_key` instead of their
+/// values.
+async fn upsert_rules<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    layout: &Layout,
+    ptype: &str,
+    rules: &Value,
+    by_key: bool,
+) -> std::result::Result<Vec<bool>, ArangorsAdapterError> {
+    let search = if by_key {
+        "{ _key: r._key }".to_owned()
+    } else {
+        layout.upsert_search(ptype)
+    };
+
+    let query = format!(
+        r#"FOR r IN @rules
+    UPSERT {}
+    INSERT r
+    UPDATE {{}}
+    IN @@collection
+    RETURN OLD == null"#,
+        search
+    );
+
+    let aql = AqlQuery::builder()
+        .query(&query)
+        .bind_var("rules", rules.clone())
+        .bind_var("@collection", collection)
+        .build();
+
+    Ok(e.run(aql).await?)
+}
+
+async fn remove_rule<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    layout: &Layout,
+    pt: &str,
+    rule: Vec<String>,
+) -> std::result::Result<bool, ArangorsAdapterError> {
+    let rule = layout.stored_values(rule);
+
+    if let Shape::Array = layout.shape {
         let aql = AqlQuery::builder()
             .query(
-                r#"FOR r IN casbin
+                r#"FOR r IN @@collection
     FILTER r.ptype == @ptype
-    FILTER r.v0 == @v0
-    FILTER r.v1 == @v1
-    FILTER r.v2 == @v2
-    FILTER r.v3 == @v3
-    FILTER r.v4 == @v4
-    FILTER r.v5 == @v5
-    REMOVE r IN casbin
+    FILTER r.rule == @rule
+    REMOVE r IN @@collection
     RETURN 1"#,
             )
             .bind_var("ptype", pt)
-            .bind_var("v0", rule[0].as_str())
-            .bind_var("v1", rule[1].as_str())
-            .bind_var("v2", rule[2].as_str())
-            .bind_var("v3", rule[3].as_str())
-            .bind_var("v4", rule[4].as_str())
-            .bind_var("v5", rule[5].as_str())
+            .bind_var("rule", rule)
+            .bind_var("@collection", collection)
             .build();
 
-        let arr: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
+        let arr: Vec<Value> = e.run(aql).await?;
 
-        Ok(!arr.is_empty())
+        return Ok(!arr.is_empty());
     }
 
-    async fn remove_policies(&self, pt: &str, rules: Vec<Vec<String>>) -> Result<bool> {
-        for rule in rules {
-            self.remove_policy(pt, rule).await?;
+    let (query, vars) = exact_removal_query(layout, pt, &rule);
+    remove_matching(e, collection, &query, &vars).await
+}
+
+async fn remove_matching<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    query: &str,
+    vars: &[(String, Value)],
+) -> std::result::Result<bool, ArangorsAdapterError> {
+    let mut bind_vars: HashMap<&str, Value> =
+        vars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+    bind_vars.insert("@collection", Value::from(collection));
+
+    let aql = AqlQuery::builder()
+        .query(query)
+        .bind_vars(bind_vars)
+        .build();
+
+    let arr: Vec<Value> = e.run(aql).await?;
+
+    Ok(!arr.is_empty())
+}
+
+/// Runs `query` on the collection and returns the documents it returns, the
+/// removed ones for queries ending with `RETURN OLD`.
+async fn run_documents<E: AqlExecutor + ?Sized>(
+    e: &E,
+    collection: &str,
+    query: &str,
+    vars: &[(String, Value)],
+) -> std::result::Result<Vec<Value>, ArangorsAdapterError> {
+    let mut bind_vars: HashMap<&str, Value> =
+        vars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+    bind_vars.insert("@collection", Value::from(collection));
+
+    let aql = AqlQuery::builder()
+        .query(query)
+        .bind_vars(bind_vars)
+        .build();
+
+    Ok(e.run(aql).await?)
+}
+
+/// Removes the documents with the given keys through the primary index, only
+/// if every one of them exists.
+const KEY_REMOVAL_QUERY: &str = r#"LET keys = UNIQUE(@keys)
+LET found = (
+    FOR k IN keys
+        FOR r IN @@collection
+            FILTER r._key == k
+            RETURN r
+)
+FOR r IN LENGTH(found) == LENGTH(keys) ? found : []
+    REMOVE r IN @@collection
+    RETURN OLD"#;
+
+/// Builds the query used by `remove_policies`: the documents matching each
+/// rule exactly are collected first, and only removed if every rule matched,
+/// so that a missing rule leaves the collection untouched.
+pub(crate) fn batch_removal_query(
+    layout: &Layout,
+    pt: &str,
+    rules: Vec<Vec<String>>,
+) -> (String, Vec<(String, Value)>) {
+    let rules: Vec<Vec<String>> = rules
+        .into_iter()
+        .map(|rule| layout.stored_values(rule))
+        .collect();
+
+    let (condition, rules) = match layout.shape {
+        Shape::Array => ("r.rule == rule".to_owned(), Value::from(rules)),
+        _ => {
+            let stored = |rule: &Vec<String>| rule.len().max(layout.width(pt));
+            let width = rules.iter().map(stored).max().unwrap_or_default();
+
+            let mut conditions = (0..width)
+                .map(|i| format!("r.{} == rule[{}]", layout.field(pt, i), i))
+                .collect::<Vec<_>>();
+            conditions.push(format!("r.{} == null", layout.field(pt, width)));
+
+            // positions past those stored with the rule must be absent
+            let padded = rules
+                .iter()
+                .map(|rule| {
+                    (0..width)
+                        .map(|i| {
+                            if i < stored(rule) {
+                                layout.value_at(rule, i)
+                            } else {
+                                Value::Null
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            (conditions.join(" AND "), Value::from(padded))
         }
+    };
 
-        Ok(true)
+    let query = format!(
+        r#"LET matches = (
+    FOR rule IN @rules
+        RETURN (
+            FOR r IN @@collection
+                FILTER r.ptype == @ptype AND {}
+                RETURN r
+        )
+)
+FOR r IN LENGTH(matches[* FILTER LENGTH(CURRENT) == 0]) == 0 ? UNIQUE(FLATTEN(matches)) : []
+    REMOVE r IN @@collection
+    RETURN OLD"#,
+        condition
+    );
+
+    (
+        query,
+        vec![
+            ("ptype".to_owned(), Value::from(pt)),
+            ("rules".to_owned(), rules),
+        ],
+    )
+}
+
+/// Builds the query used by `remove_filtered_policy`, returning the removed
+/// documents: the rules of the ptype whose values from `field_index` on match
+/// every non-empty field value, at any position. An
+/// empty field value matches anything, like casbin does in memory, so rules
+/// storing absent positions as `""` or `null` are both matched.
+pub(crate) fn filtered_removal_query(
+    layout: &Layout,
+    pt: &str,
+    field_index: usize,
+    field_values: &[String],
+) -> (String, Vec<(String, Value)>) {
+    let mut conditions = vec!["r.ptype == @ptype".to_owned()];
+    let mut vars = vec![("ptype".to_owned(), Value::from(pt))];
+
+    for (i, value) in field_values.iter().enumerate() {
+        if !value.is_empty() {
+            conditions.push(format!(
+                "r.{} == @f{}",
+                layout.field(pt, field_index + i),
+                i
+            ));
+            vars.push((format!("f{}", i), Value::from(value.as_str())));
+        }
     }
 
-    async fn remove_filtered_policy(&self, pt: &str, field_index: usize, field_values: Vec<String>)
-        -> Result<bool> {
-        let field_values = normalize_casbin_rule(field_values, field_index);
-
-        let aql = if field_index == 5 {
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v5 == NOT_NULL(@f0, r.v5)
-            REMOVE r IN casbin
-            RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .build()
-        }else if field_index == 4 {
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v4 == NOT_NULL(@f0, r.v4)
-            FILTER r.v5 == NOT_NULL(@f1, r.v5)
-            REMOVE r IN casbin
-            RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .bind_var("f1", if field_values[1].is_empty() { None } else { Some(field_values[1].as_str()) })
-                .build()
-        }else if field_index == 3 {
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v3 == NOT_NULL(@f0, r.v3)
-            FILTER r.v4 == NOT_NULL(@f1, r.v4)
-            FILTER r.v5 == NOT_NULL(@f2, r.v5)
-            REMOVE r IN casbin
-            RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .bind_var("f1", if field_values[1].is_empty() { None } else { Some(field_values[1].as_str()) })
-                .bind_var("f2", if field_values[2].is_empty() { None } else { Some(field_values[2].as_str()) })
-                .build()
-        }else if field_index == 2{
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v2 == NOT_NULL(@f0, r.v2)
-            FILTER r.v3 == NOT_NULL(@f1, r.v3)
-            FILTER r.v4 == NOT_NULL(@f2, r.v4)
-            FILTER r.v5 == NOT_NULL(@f3, r.v5)
-            REMOVE r IN casbin
-            RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .bind_var("f1", if field_values[1].is_empty() { None } else { Some(field_values[1].as_str()) })
-                .bind_var("f2", if field_values[2].is_empty() { None } else { Some(field_values[2].as_str()) })
-                .bind_var("f3", if field_values[3].is_empty() { None } else { Some(field_values[3].as_str()) })
-                .build()
-        }else if field_index == 1{
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v1 == NOT_NULL(@f0, r.v1)
-            FILTER r.v2 == NOT_NULL(@f1, r.v2)
-            FILTER r.v3 == NOT_NULL(@f2, r.v3)
-            FILTER r.v4 == NOT_NULL(@f3, r.v4)
-            FILTER r.v5 == NOT_NULL(@f4, r.v5)
-            REMOVE r IN casbin
-            RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .bind_var("f1", if field_values[1].is_empty() { None } else { Some(field_values[1].as_str()) })
-                .bind_var("f2", if field_values[2].is_empty() { None } else { Some(field_values[2].as_str()) })
-                .bind_var("f3", if field_values[3].is_empty() { None } else { Some(field_values[3].as_str()) })
-                .bind_var("f4", if field_values[4].is_empty() { None } else { Some(field_values[4].as_str()) })
-                .build()
-        }else{
-            AqlQuery::builder()
-                .query(r#"FOR r IN casbin
-            FILTER r.ptype == @ptype
-            FILTER r.v0 == NOT_NULL(@f0, r.v0)
-            FILTER r.v1 == NOT_NULL(@f1, r.v1)
-            FILTER r.v2 == NOT_NULL(@f2, r.v2)
-            FILTER r.v3 == NOT_NULL(@f3, r.v3)
-            FILTER r.v4 == NOT_NULL(@f4, r.v4)
-            FILTER r.v5 == NOT_NULL(@f5, r.v5)
-            REMOVE r IN casbin
-                RETURN 1"#)
-                .bind_var("ptype", pt)
-                .bind_var("f0", if field_values[0].is_empty() { None } else { Some(field_values[0].as_str()) })
-                .bind_var("f1", if field_values[1].is_empty() { None } else { Some(field_values[1].as_str()) })
-                .bind_var("f2", if field_values[2].is_empty() { None } else { Some(field_values[2].as_str()) })
-                .bind_var("f3", if field_values[3].is_empty() { None } else { Some(field_values[3].as_str()) })
-                .bind_var("f4", if field_values[4].is_empty() { None } else { Some(field_values[4].as_str()) })
-                .bind_var("f5", if field_values[5].is_empty() { None } else { Some(field_values[5].as_str()) })
-                .build()
-        };
-
-        let arr: Vec<serde_json::value::Value> = self
-            .aql_query(aql)
-            .await
-            .map_err(|e| AdapterError(Box::new(e)))?;
-        Ok(!arr.is_empty())
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    REMOVE r IN @@collection\n    RETURN OLD",
+        conditions.join(" AND ")
+    );
+
+    (query, vars)
+}
+
+/// Builds the query used by `remove_matching_policy`: the rules of the ptype
+/// whose values from `field_index` on satisfy every matcher, removed and
+/// returned, or only returned on a dry run.
+pub(crate) fn matching_policy_query(
+    layout: &Layout,
+    pt: &str,
+    field_index: usize,
+    matchers: &[FieldMatcher],
+    dry_run: bool,
+) -> (String, Vec<(String, Value)>) {
+    let mut conditions = vec!["r.ptype == @ptype".to_owned()];
+    let mut vars = vec![("ptype".to_owned(), Value::from(pt))];
+
+    for (i, matcher) in matchers.iter().enumerate() {
+        let field = format!("r.{}", layout.field(pt, field_index + i));
+        if let Some((condition, value)) = matcher.condition(&field, &format!("m{}", i)) {
+            conditions.push(condition);
+            vars.push((format!("m{}", i), value));
+        }
+    }
+
+    let action = if dry_run {
+        "RETURN r"
+    } else {
+        "REMOVE r IN @@collection\n    RETURN OLD"
+    };
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    {}",
+        conditions.join(" AND "),
+        action
+    );
+
+    (query, vars)
+}
+
+/// Builds the query removing the rule whose values are exactly `rule`, for
+/// layouts storing one property per value: every stored position must hold
+/// the value or what absent ones are stored as, and the next one must be
+/// absent.
+pub(crate) fn exact_removal_query(
+    layout: &Layout,
+    pt: &str,
+    rule: &[String],
+) -> (String, Vec<(String, Value)>) {
+    let width = rule.len().max(layout.width(pt));
+
+    let mut conditions = vec!["r.ptype == @ptype".to_owned()];
+    let mut vars = vec![("ptype".to_owned(), Value::from(pt))];
+
+    for i in 0..width {
+        conditions.push(format!("r.{} == @v{}", layout.field(pt, i), i));
+        vars.push((format!("v{}", i), layout.value_at(rule, i)));
+    }
+    conditions.push(format!("r.{} == null", layout.field(pt, width)));
+
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    REMOVE r IN @@collection\n    RETURN 1",
+        conditions.join(" AND ")
+    );
+
+    (query, vars)
+}
+
+/// Builds the query removing the vertices with the given keys that no edge
+/// starts or ends at, or holds as its domain.
+pub(crate) fn orphan_removal_query(layout: &Layout) -> String {
+    let mut conditions = vec!["e._from == v._id".to_owned(), "e._to == v._id".to_owned()];
+    conditions.extend(
+        layout
+            .domain_fields()
+            .into_iter()
+            .map(|field| format!("e.{} == v.name", field)),
+    );
+
+    format!(
+        r#"FOR v IN @@vertices
+    FILTER v._key IN @keys
+    LET linked = (
+        FOR e IN @@edges
+            FILTER {}
+            LIMIT 1
+            RETURN 1
+    )
+    FILTER LENGTH(linked) == 0
+    REMOVE v IN @@vertices"#,
+        conditions.join(" OR ")
+    )
+}
+
+/// Builds the query finding a rule padded with `""`, `None` if the layout
+/// doesn't pad rules.
+pub(crate) fn padded_rule_query(layout: &Layout) -> Option<(String, Vec<(String, Value)>)> {
+    let mut vars = vec![];
+    let mut branches = vec![];
+
+    for (i, (ptype, last, marker)) in layout.padded_positions().into_iter().enumerate() {
+        let mut conditions = vec![];
+        if let Some(ptype) = ptype {
+            conditions.push(format!("r.ptype == @t{}", i));
+            vars.push((format!("t{}", i), Value::from(ptype)));
+        }
+        conditions.push(format!("r.{} == \"\"", last));
+        conditions.push(format!("NOT HAS(r, @n{})", i));
+        vars.push((format!("n{}", i), Value::from(marker)));
+
+        branches.push(format!("({})", conditions.join(" AND ")));
+    }
+
+    if branches.is_empty() {
+        return None;
+    }
+
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    LIMIT 1\n    RETURN 1",
+        branches.join(" OR ")
+    );
+
+    Some((query, vars))
+}
+
+/// Builds the query used by `load_filtered_policy_with`: every group becomes
+/// one OR-ed branch matching its ptype and each of its fields.
+pub(crate) fn group_policy_query(
+    layout: &Layout,
+    groups: &[&FilterGroup],
+) -> (String, Vec<(String, Value)>) {
+    let mut vars = vec![];
+    let mut branches = vec![];
+
+    for (i, group) in groups.iter().enumerate() {
+        let mut conditions = vec![format!("r.ptype == @t{}", i)];
+        vars.push((format!("t{}", i), Value::from(group.ptype())));
+
+        for (j, (index, matcher)) in group.fields().iter().enumerate() {
+            let field = format!("r.{}", layout.field(group.ptype(), *index));
+            let var = format!("m{}_{}", i, j);
+            if let Some((condition, value)) = matcher.condition(&field, &var) {
+                conditions.push(condition);
+                vars.push((var, value));
+            }
+        }
+
+        branches.push(format!("({})", conditions.join(" AND ")));
     }
+
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    FILTER {}\n    RETURN r",
+        branches.join(" OR "),
+        validity_filter("r")
+    );
+
+    (query, vars)
 }
 
-fn normalize_casbin_rule(mut rule: Vec<String>, field_index: usize) -> Vec<String> {
-    rule.resize(6 - field_index, String::from(""));
-    rule
+fn from_documents(layout: &Layout, documents: Vec<Value>) -> Result<Vec<CasbinRule>> {
+    documents
+        .into_iter()
+        .map(|d| layout.decode(d))
+        .collect::<serde_json::Result<Vec<CasbinRule>>>()
+        .map_err(|e| ArangorsAdapterError::from(e).into())
+}
+
+/// Builds the query used by `load_filtered_policy`. Every `(ptype, values)`
+/// pair becomes one OR-ed branch matching the ptype and each non-empty value
+/// at its position, so the unique index can be used.
+pub(crate) fn filtered_policy_query(
+    layout: &Layout,
+    filters: &[(String, Vec<String>)],
+) -> (String, Vec<(String, Value)>) {
+    let mut vars = vec![];
+    let mut branches = vec![];
+
+    for (i, (ptype, values)) in filters.iter().enumerate() {
+        let mut conditions = vec![format!("r.ptype == @t{}", i)];
+        vars.push((format!("t{}", i), Value::from(ptype.as_str())));
+
+        for (j, value) in values.iter().enumerate() {
+            if !value.is_empty() {
+                conditions.push(format!("r.{} == @f{}_{}", layout.field(ptype, j), i, j));
+                vars.push((format!("f{}_{}", i, j), Value::from(value.as_str())));
+            }
+        }
+
+        branches.push(format!("({})", conditions.join(" AND ")));
+    }
+
+    let query = format!(
+        "FOR r IN @@collection\n    FILTER {}\n    FILTER {}\n    RETURN r",
+        branches.join(" OR "),
+        validity_filter("r")
+    );
+
+    (query, vars)
 }
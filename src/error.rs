@@ -0,0 +1,150 @@
+use arangors::ClientError;
+use casbin::error::AdapterError;
+use thiserror::Error;
+
+const ERROR_FORBIDDEN: u16 = 11;
+const ERROR_LOCK_TIMEOUT: u16 = 18;
+#[cfg(feature = "watcher")]
+pub(crate) const ERROR_ARANGO_CONFLICT: u16 = 1200;
+pub(crate) const ERROR_ARANGO_DATA_SOURCE_NOT_FOUND: u16 = 1203;
+pub(crate) const ERROR_ARANGO_DUPLICATE_NAME: u16 = 1207;
+pub(crate) const ERROR_ARANGO_UNIQUE_CONSTRAINT_VIOLATED: u16 = 1210;
+const ERROR_CLUSTER_TIMEOUT: u16 = 1457;
+pub(crate) const ERROR_GRAPH_NOT_FOUND: u16 = 1924;
+pub(crate) const ERROR_GRAPH_DUPLICATE: u16 = 1925;
+
+/// The ArangoDB error number of a client error, when it comes from the
+/// server.
+pub(crate) fn error_num(e: &ClientError) -> Option<u16> {
+    match e {
+        ClientError::Arango(e) => Some(e.error_num()),
+        _ => None,
+    }
+}
+
+/// Errors raised by the adapter.
+///
+/// They reach the caller wrapped in a `casbin::error::AdapterError`, use
+/// [`ArangorsAdapterError::from_casbin`] to get them back.
+#[derive(Debug, Error)]
+pub enum ArangorsAdapterError {
+    #[error("rule already exists: {message} ({error_num})")]
+    DuplicateRule { error_num: u16, message: String },
+    #[error("collection not found: {message} ({error_num})")]
+    CollectionNotFound { error_num: u16, message: String },
+    #[error("authentication failed: {message} ({error_num})")]
+    Unauthorized { error_num: u16, message: String },
+    #[error("operation timed out: {message}")]
+    Timeout {
+        error_num: Option<u16>,
+        message: String,
+    },
+    #[error("cannot (de)serialize rules: {0}")]
+    Serialization(#[from] serde_json::Error),
+    #[error("index `{index}` on collection `{collection}` must be a unique persistent index on [{}], found [{}]", .expected.join(", "), .fields.join(", "))]
+    IndexMismatch {
+        collection: String,
+        index: String,
+        expected: Vec<String>,
+        fields: Vec<String>,
+    },
+    #[error("collection `{collection}` holds rules padded with \"\", convert them with `migrate_layout` before storing absent positions as null")]
+    PaddedRules { collection: String },
+    #[error("a transaction is already active")]
+    TransactionAlreadyActive,
+    #[error("no transaction is active")]
+    TransactionNotActive,
+    #[error("the audit collection is not enabled")]
+    AuditNotEnabled,
+    #[error("policy versions are not enabled")]
+    VersionsNotEnabled,
+    #[error("invalid ptype `{ptype}`")]
+    InvalidPtype { ptype: String },
+    #[error("policy version not found: {version}")]
+    VersionNotFound { version: String },
+    #[error("policy version `{label}` was not created")]
+    VersionNotCreated { label: String },
+    #[error("ArangoDB error: {message} ({error_num})")]
+    Arango {
+        code: u16,
+        error_num: u16,
+        message: String,
+    },
+    #[error(transparent)]
+    Client(ClientError),
+}
+
+impl ArangorsAdapterError {
+    /// Returns the adapter error carried by a casbin error, if any.
+    pub fn from_casbin(e: &casbin::Error) -> Option<&Self> {
+        match e {
+            casbin::Error::AdapterError(AdapterError(e)) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+
+    /// The ArangoDB error number, when the error comes from the server.
+    pub fn error_num(&self) -> Option<u16> {
+        match self {
+            ArangorsAdapterError::DuplicateRule { error_num, .. }
+            | ArangorsAdapterError::CollectionNotFound { error_num, .. }
+            | ArangorsAdapterError::Unauthorized { error_num, .. }
+            | ArangorsAdapterError::Arango { error_num, .. } => Some(*error_num),
+            ArangorsAdapterError::Timeout { error_num, .. } => *error_num,
+            _ => None,
+        }
+    }
+}
+
+impl From<ClientError> for ArangorsAdapterError {
+    fn from(e: ClientError) -> Self {
+        match e {
+            ClientError::Arango(e) => {
+                let error_num = e.error_num();
+                let message = e.message().to_owned();
+
+                match (e.code(), error_num) {
+                    (_, ERROR_ARANGO_UNIQUE_CONSTRAINT_VIOLATED) => {
+                        ArangorsAdapterError::DuplicateRule { error_num, message }
+                    }
+                    (_, ERROR_ARANGO_DATA_SOURCE_NOT_FOUND) => {
+                        ArangorsAdapterError::CollectionNotFound { error_num, message }
+                    }
+                    (401, _) | (_, ERROR_FORBIDDEN) => {
+                        ArangorsAdapterError::Unauthorized { error_num, message }
+                    }
+                    (408, _) | (504, _) | (_, ERROR_LOCK_TIMEOUT) | (_, ERROR_CLUSTER_TIMEOUT) => {
+                        ArangorsAdapterError::Timeout {
+                            error_num: Some(error_num),
+                            message,
+                        }
+                    }
+                    (code, _) => ArangorsAdapterError::Arango {
+                        code,
+                        error_num,
+                        message,
+                    },
+                }
+            }
+            ClientError::Serde(e) => ArangorsAdapterError::Serialization(e),
+            ClientError::HttpClient(e) if is_timeout(&e.to_string()) => {
+                ArangorsAdapterError::Timeout {
+                    error_num: None,
+                    message: e.to_string(),
+                }
+            }
+            e => ArangorsAdapterError::Client(e),
+        }
+    }
+}
+
+impl From<ArangorsAdapterError> for casbin::Error {
+    fn from(e: ArangorsAdapterError) -> Self {
+        AdapterError(Box::new(e)).into()
+    }
+}
+
+// the HTTP client only hands over the debug output of its error
+fn is_timeout(message: &str) -> bool {
+    message.contains("TimedOut") || message.contains("timed out")
+}
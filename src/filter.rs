@@ -0,0 +1,196 @@
+use crate::ArangorsAdapter;
+use arangors::uclient::ClientExt;
+use casbin::{CoreApi, Result};
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+
+pub(crate) type SharedLoaded = Arc<RwLock<LoadedPolicy>>;
+
+/// How one value of a rule is matched, by `remove_matching_policy` or an
+/// [`ArangoFilter`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldMatcher {
+    /// Any value, like an empty field value of `remove_filtered_policy`.
+    Any,
+    /// This exact value.
+    Exact(String),
+    /// Any of these values.
+    In(Vec<String>),
+    /// Values starting with the prefix.
+    Prefix(String),
+    /// Values matching a glob, where `*` stands for any sequence of characters
+    /// and `?` for a single one: `/projects/42/*`.
+    Glob(String),
+    /// Values matching an ArangoDB regular expression.
+    Regex(String),
+}
+
+impl FieldMatcher {
+    /// The AQL condition matching the attribute `field` against the bind
+    /// parameter `@{var}`, along with its value. `None` for [`FieldMatcher::Any`].
+    pub(crate) fn condition(&self, field: &str, var: &str) -> Option<(String, Value)> {
+        let (condition, value) = match self {
+            FieldMatcher::Any => return None,
+            FieldMatcher::Exact(value) => (
+                format!("{} == @{}", field, var),
+                Value::from(value.as_str()),
+            ),
+            FieldMatcher::In(values) => (
+                format!("{} IN @{}", field, var),
+                Value::from(values.clone()),
+            ),
+            FieldMatcher::Prefix(prefix) => (
+                format!("STARTS_WITH({}, @{})", field, var),
+                Value::from(prefix.as_str()),
+            ),
+            FieldMatcher::Glob(glob) => (
+                format!("LIKE({}, @{})", field, var),
+                Value::from(glob_to_like(glob)),
+            ),
+            FieldMatcher::Regex(regex) => (
+                format!("REGEX_TEST({}, @{})", field, var),
+                Value::from(regex.as_str()),
+            ),
+        };
+
+        Some((condition, value))
+    }
+}
+
+/// A filter for `ArangorsAdapter::load_filtered_policy_with`, evaluated by
+/// ArangoDB: the rules matching any of its groups are loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArangoFilter {
+    groups: Vec<FilterGroup>,
+}
+
+/// What the model was last loaded with, to tell whether it holds a part of
+/// the policy and which.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LoadedPolicy {
+    #[default]
+    Nothing,
+    Whole,
+    Filtered(Vec<ArangoFilter>),
+}
+
+/// The rules of one ptype whose values satisfy every matcher of the group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilterGroup {
+    ptype: String,
+    fields: Vec<(usize, FieldMatcher)>,
+}
+
+impl ArangoFilter {
+    /// A filter without groups, matching no rule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also loads the rules matching `group`.
+    pub fn with_group(mut self, group: FilterGroup) -> Self {
+        self.groups.push(group);
+        self
+    }
+
+    pub(crate) fn groups(&self) -> &[FilterGroup] {
+        &self.groups
+    }
+}
+
+impl FilterGroup {
+    /// Every rule of the ptype, until fields are matched.
+    pub fn new(ptype: &str) -> Self {
+        Self {
+            ptype: ptype.to_owned(),
+            fields: vec![],
+        }
+    }
+
+    /// Requires the value at `index` to match.
+    pub fn with_field(mut self, index: usize, matcher: FieldMatcher) -> Self {
+        self.fields.push((index, matcher));
+        self
+    }
+
+    /// The group of a `casbin::Filter`: non-empty values are matched exactly.
+    pub(crate) fn exact(ptype: &str, values: &[String]) -> Self {
+        values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.is_empty())
+            .fold(Self::new(ptype), |group, (index, value)| {
+                group.with_field(index, FieldMatcher::Exact(value.clone()))
+            })
+    }
+
+    pub(crate) fn ptype(&self) -> &str {
+        &self.ptype
+    }
+
+    pub(crate) fn fields(&self) -> &[(usize, FieldMatcher)] {
+        &self.fields
+    }
+}
+
+/// Translates a glob to a `LIKE` pattern, escaping the characters `LIKE`
+/// gives a meaning to.
+pub(crate) fn glob_to_like(glob: &str) -> String {
+    let mut like = String::with_capacity(glob.len());
+
+    for c in glob.chars() {
+        match c {
+            '*' => like.push('%'),
+            '?' => like.push('_'),
+            '%' | '_' | '\\' => {
+                like.push('\\');
+                like.push(c);
+            }
+            c => like.push(c),
+        }
+    }
+
+    like
+}
+
+/// Loads the rules of more filters into the model of an `Enforcer`, see
+/// [`ArangorsAdapter::load_incremental_filtered_policy`].
+///
+/// Like a [`crate::TransactionHandle`], the handle shares its state with the
+/// adapter it was obtained from and keeps working after the adapter was
+/// moved into the enforcer, so the filters it loads are known to that
+/// adapter and to `Enforcer::is_filtered`.
+pub struct FilterHandle<C: ClientExt> {
+    adapter: ArangorsAdapter<C>,
+}
+
+impl<C: ClientExt + Send> FilterHandle<C> {
+    pub(crate) fn new(adapter: ArangorsAdapter<C>) -> Self {
+        Self { adapter }
+    }
+
+    /// Adds the rules matching the filter to the model of the enforcer,
+    /// rebuilds its role links, and returns whether the filter was loaded.
+    pub async fn load_incremental_filtered_policy<E: CoreApi>(
+        &self,
+        e: &mut E,
+        filter: &ArangoFilter,
+    ) -> Result<bool> {
+        if !self
+            .adapter
+            .load_incremental(e.get_mut_model(), filter)
+            .await?
+        {
+            return Ok(false);
+        }
+
+        e.build_role_links()?;
+        Ok(true)
+    }
+
+    /// The filters the model was loaded with, see
+    /// [`ArangorsAdapter::loaded_filters`].
+    pub fn loaded_filters(&self) -> Vec<ArangoFilter> {
+        self.adapter.loaded_filters()
+    }
+}
@@ -0,0 +1,205 @@
+use crate::casbin_dao::{AqlExecutor, CasbinDao};
+use crate::error::{error_num, ERROR_GRAPH_DUPLICATE, ERROR_GRAPH_NOT_FOUND};
+use crate::layout::{Layout, DOMAIN};
+use crate::schema;
+use crate::{ArangorsAdapterError, CasbinRule};
+use arangors::graph::{EdgeDefinition, Graph};
+use arangors::uclient::ClientExt;
+use arangors::Database;
+use casbin::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+const MAX_KEY_LENGTH: usize = 254;
+
+/// Where grouping rules live when they are stored as a named graph: each
+/// subject, role or domain is a vertex and each rule an edge from its first to
+/// its second value. The remaining values, such as the domain, stay on the
+/// edge.
+#[derive(Clone)]
+pub(crate) struct RoleGraph {
+    pub name: String,
+    pub vertices: String,
+    pub edges: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Vertex {
+    pub _key: String,
+    pub name: String,
+}
+
+impl RoleGraph {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            vertices: format!("{}_vertices", name),
+            edges: format!("{}_edges", name),
+        }
+    }
+
+    /// Links the rule to the vertices of its first two values.
+    pub fn to_edge(&self, mut rule: CasbinRule) -> CasbinRule {
+        rule._from = Some(self.vertex_id(rule.value(0)));
+        rule._to = Some(self.vertex_id(rule.value(1)));
+        rule
+    }
+
+    pub fn vertex_id(&self, name: &str) -> String {
+        format!("{}/{}", self.vertices, vertex_key(name))
+    }
+
+    /// The vertices the edges point to and their domains, without
+    /// duplicates.
+    pub fn vertices_of(&self, edges: &[CasbinRule]) -> Vec<Vertex> {
+        edges
+            .iter()
+            .flat_map(|e| vertex_names(&e.values))
+            .map(|name| (vertex_key(name), name))
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(_key, name)| Vertex {
+                _key,
+                name: name.to_owned(),
+            })
+            .collect()
+    }
+}
+
+/// The values of a grouping rule standing for vertices: the two ends of its
+/// edge, and its domain if it has one.
+fn vertex_names(values: &[String]) -> impl Iterator<Item = &str> {
+    let value = |i: usize| values.get(i).map_or("", String::as_str);
+
+    [value(0), value(1)]
+        .into_iter()
+        .chain(Some(value(DOMAIN)).filter(|domain| !domain.is_empty()))
+}
+
+/// Vertex keys keep the name readable: characters ArangoDB doesn't allow in a
+/// key are percent-encoded, and overly long names are hashed.
+pub(crate) fn vertex_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"_-:.@()+,=;$!*'".contains(&b) {
+            key.push(b as char);
+        } else {
+            key.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+        return format!("{:x}", Sha256::digest(name.as_bytes()));
+    }
+
+    key
+}
+
+/// Creates the named graph with its vertex and edge collections if needed,
+/// and the unique index on the edges.
+pub(crate) async fn ensure_graph<C: ClientExt>(
+    database: &Database<C>,
+    graph: &RoleGraph,
+    layout: &Layout,
+) -> Result<()> {
+    match database.graph(&graph.name).await {
+        Ok(_) => {}
+        Err(e) if error_num(&e) == Some(ERROR_GRAPH_NOT_FOUND) => {
+            let definition = Graph::builder()
+                .name(graph.name.clone())
+                .edge_definitions(vec![EdgeDefinition {
+                    collection: graph.edges.clone(),
+                    from: vec![graph.vertices.clone()],
+                    to: vec![graph.vertices.clone()],
+                }])
+                .build();
+
+            match database.create_graph(definition, false).await {
+                Ok(_) => {}
+                // another instance created it in the meantime
+                Err(e) if error_num(&e) == Some(ERROR_GRAPH_DUPLICATE) => {}
+                Err(e) => return Err(ArangorsAdapterError::from(e).into()),
+            }
+        }
+        Err(e) => return Err(ArangorsAdapterError::from(e).into()),
+    }
+
+    schema::ensure_unique_index(database, &graph.edges, layout).await
+}
+
+/// Stores the edges and their vertices and returns the edges inserted. With
+/// `upsert`, edges already stored are skipped, matched on their key if
+/// `Some(true)`.
+pub(crate) async fn insert_edges<E: AqlExecutor>(
+    e: &E,
+    graph: &RoleGraph,
+    layout: &Layout,
+    edges: Vec<CasbinRule>,
+    upsert: Option<bool>,
+) -> Result<Vec<CasbinRule>> {
+    let vertices = graph.vertices_of(&edges);
+
+    e.upsert_vertices(&graph.vertices, vertices).await?;
+
+    match upsert {
+        Some(by_key) => e.upsert_policies(&graph.edges, layout, edges, by_key).await,
+        None => e
+            .add_policies(&graph.edges, layout, edges.clone())
+            .await
+            .map(|_| edges),
+    }
+}
+
+/// Removes the vertices of the removed grouping rules that are left without
+/// edges, and aren't the domain of any either.
+pub(crate) async fn remove_orphans<E: AqlExecutor>(
+    e: &E,
+    graph: &RoleGraph,
+    layout: &Layout,
+    removed: &[Vec<String>],
+) -> Result<()> {
+    let keys = removed
+        .iter()
+        .flat_map(|rule| vertex_names(rule))
+        .map(vertex_key)
+        .collect::<BTreeSet<_>>();
+
+    e.remove_orphan_vertices(
+        &graph.vertices,
+        &graph.edges,
+        layout,
+        keys.into_iter().collect(),
+    )
+    .await
+}
+
+/// Replaces the rules of the policy collection and the edges and vertices of
+/// the graph.
+pub(crate) async fn replace_all<E: AqlExecutor>(
+    e: &E,
+    collection: &str,
+    graph: &RoleGraph,
+    layout: &Layout,
+    rules: Vec<CasbinRule>,
+    edges: Vec<CasbinRule>,
+) -> Result<()> {
+    let vertices = graph.vertices_of(&edges);
+
+    e.save_policy(collection, layout, rules).await?;
+    e.save_policy(&graph.edges, layout, edges).await?;
+    e.clear_policy(&graph.vertices).await?;
+    e.upsert_vertices(&graph.vertices, vertices).await
+}
+
+/// Removes every rule, edge and vertex.
+pub(crate) async fn clear_all<E: AqlExecutor>(
+    e: &E,
+    collection: &str,
+    graph: &RoleGraph,
+) -> Result<()> {
+    e.clear_policy(collection).await?;
+    e.clear_policy(&graph.edges).await?;
+    e.clear_policy(&graph.vertices).await
+}
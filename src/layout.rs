@@ -0,0 +1,398 @@
+use crate::casbin_dao::{AqlExecutor, CasbinDao};
+use crate::model::CasbinRule;
+use casbin::{Model, Result};
+use serde::de::Error;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// How the values of a rule are laid out in its document.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DocumentLayout {
+    /// One property per value, `{ptype, v0, .., v5}`, padded with `""` or
+    /// `null`. Values past the sixth go to an `extra` array.
+    #[default]
+    Columns,
+    /// All the values in a single array, `{ptype, rule: [..]}`, without
+    /// padding.
+    Array,
+}
+
+const COLUMN_FIELDS: [&str; 8] = ["ptype", "v0", "v1", "v2", "v3", "v4", "v5", "extra"];
+const ARRAY_FIELDS: [&str; 2] = ["ptype", "rule"];
+
+/// Positions stored by the columns layout even when a rule is shorter.
+const COLUMNS: usize = 6;
+
+/// Position of the domain in a grouping rule.
+pub(crate) const DOMAIN: usize = 2;
+
+/// Names given to the positions of a role definition, whose tokens are `_`.
+const ROLE_FIELDS: [&str; 3] = ["user", "role", "domain"];
+
+/// The field names of each ptype, in position order.
+pub(crate) type FieldNames = BTreeMap<String, Vec<String>>;
+
+/// How rules are written: the shape of the documents and what absent
+/// positions are stored as.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Layout {
+    pub shape: Shape,
+    /// Absent positions are stored as `null` instead of `""`, so that empty
+    /// values are kept.
+    pub null_absent: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Shape {
+    #[default]
+    Columns,
+    Array,
+    /// One property per value, named after the tokens of the model:
+    /// `{ptype: "p", sub: "alice", obj: "data1", act: "read"}`. Values without
+    /// a token are stored as `v{position}`.
+    Named(FieldNames),
+}
+
+impl From<DocumentLayout> for Layout {
+    fn from(layout: DocumentLayout) -> Self {
+        let shape = match layout {
+            DocumentLayout::Columns => Shape::Columns,
+            DocumentLayout::Array => Shape::Array,
+        };
+
+        Layout {
+            shape,
+            null_absent: false,
+        }
+    }
+}
+
+impl Layout {
+    /// The named layout with the tokens of the `[policy_definition]` and
+    /// `[role_definition]` sections of the model.
+    pub fn named(m: &dyn Model) -> Self {
+        let mut names = FieldNames::new();
+
+        for sec in ["p", "g"] {
+            for (ptype, assertion) in m.get_model().get(sec).into_iter().flatten() {
+                let fields = assertion
+                    .value
+                    .split(',')
+                    .map(str::trim)
+                    .enumerate()
+                    .map(|(i, token)| match token {
+                        "_" => ROLE_FIELDS
+                            .get(i)
+                            .map(|f| f.to_string())
+                            .unwrap_or_else(|| format!("v{}", i)),
+                        token => token.to_owned(),
+                    })
+                    .collect();
+
+                names.insert(ptype.clone(), fields);
+            }
+        }
+
+        Layout {
+            shape: Shape::Named(names),
+            null_absent: false,
+        }
+    }
+
+    /// The values of a rule as they are stored: trailing empty values can't be
+    /// told apart from absent ones and are dropped, unless absent positions
+    /// are stored as `null`.
+    pub(crate) fn stored_values(&self, mut values: Vec<String>) -> Vec<String> {
+        if !self.null_absent {
+            while values.last().is_some_and(String::is_empty) {
+                values.pop();
+            }
+        }
+
+        values
+    }
+
+    /// The rule of the ptype with the given values, `None` if it has none.
+    pub(crate) fn rule(&self, ptype: &str, values: &[String]) -> Option<CasbinRule> {
+        let values = self.stored_values(values.to_vec());
+        if ptype.trim().is_empty() || values.is_empty() {
+            return None;
+        }
+
+        Some(CasbinRule {
+            _key: None,
+            _from: None,
+            _to: None,
+            ptype: ptype.to_owned(),
+            values,
+            valid_from: None,
+            valid_until: None,
+        })
+    }
+
+    /// How many positions of a rule of the ptype are stored, however many
+    /// values it has.
+    pub(crate) fn width(&self, ptype: &str) -> usize {
+        match &self.shape {
+            Shape::Columns => COLUMNS,
+            Shape::Array => 0,
+            Shape::Named(names) => names.get(ptype).map_or(0, Vec::len),
+        }
+    }
+
+    /// The value stored at `index`, `""` or `null` past the last one.
+    pub(crate) fn value_at(&self, values: &[String], index: usize) -> Value {
+        match values.get(index) {
+            Some(value) => Value::from(value.as_str()),
+            None if self.null_absent => Value::Null,
+            None => Value::from(""),
+        }
+    }
+
+    /// Whether absent positions are stored as `null` and the rule fills every
+    /// stored position with a trailing `""`: it would look padded with `""`,
+    /// so a `null` marker is stored past its positions.
+    fn fills_with_empty(&self, ptype: &str, values: &[String]) -> bool {
+        let width = self.width(ptype);
+        self.null_absent && width > 0 && values.len() == width && values[width - 1].is_empty()
+    }
+
+    /// The attribute following the positions stored for every rule of the
+    /// ptype.
+    fn padding_marker(&self, ptype: &str) -> String {
+        match &self.shape {
+            Shape::Columns => "extra".to_owned(),
+            _ => self.field_name(ptype, self.width(ptype)),
+        }
+    }
+
+    /// For each ptype whose rules are padded, `None` standing for all of them
+    /// in the columns layout: the attribute of its last stored position, ready
+    /// to follow a `.` in AQL, and the name of the attribute following it. A
+    /// rule padded with `""` holds `""` in the former and lacks the latter.
+    pub(crate) fn padded_positions(&self) -> Vec<(Option<String>, String, String)> {
+        match &self.shape {
+            Shape::Columns => vec![(None, self.field("", COLUMNS - 1), self.padding_marker(""))],
+            Shape::Array => vec![],
+            Shape::Named(names) => names
+                .iter()
+                .filter(|(_, fields)| !fields.is_empty())
+                .map(|(ptype, fields)| {
+                    (
+                        Some(ptype.clone()),
+                        self.field(ptype, fields.len() - 1),
+                        self.padding_marker(ptype),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn encode(&self, rule: &CasbinRule) -> serde_json::Result<Value> {
+        let mut document = Map::new();
+        for (key, value) in [
+            ("_key", &rule._key),
+            ("_from", &rule._from),
+            ("_to", &rule._to),
+        ] {
+            if let Some(value) = value {
+                document.insert(key.to_owned(), Value::from(value.as_str()));
+            }
+        }
+        document.insert("ptype".to_owned(), Value::from(rule.ptype.as_str()));
+
+        match &self.shape {
+            Shape::Array => {
+                document.insert("rule".to_owned(), Value::from(rule.values.clone()));
+            }
+            Shape::Columns => {
+                for i in 0..COLUMNS {
+                    document.insert(format!("v{}", i), self.value_at(&rule.values, i));
+                }
+                if let Some(extra) = rule.values.get(COLUMNS..).filter(|e| !e.is_empty()) {
+                    document.insert("extra".to_owned(), Value::from(extra.to_vec()));
+                }
+            }
+            Shape::Named(_) => {
+                for i in 0..rule.values.len().max(self.width(&rule.ptype)) {
+                    document.insert(
+                        self.field_name(&rule.ptype, i),
+                        self.value_at(&rule.values, i),
+                    );
+                }
+            }
+        }
+
+        // otherwise the trailing "" would look like padding
+        if self.fills_with_empty(&rule.ptype, &rule.values) {
+            document.insert(self.padding_marker(&rule.ptype), Value::Null);
+        }
+
+        for (key, value) in [
+            ("valid_from", rule.valid_from),
+            ("valid_until", rule.valid_until),
+        ] {
+            if let Some(value) = value {
+                document.insert(key.to_owned(), Value::from(value));
+            }
+        }
+
+        Ok(Value::Object(document))
+    }
+
+    pub(crate) fn encode_all(&self, rules: &[CasbinRule]) -> serde_json::Result<Value> {
+        rules
+            .iter()
+            .map(|r| self.encode(r))
+            .collect::<serde_json::Result<Vec<Value>>>()
+            .map(Value::from)
+    }
+
+    /// Reads a rule written with this layout. Both `null` and `""` are read as
+    /// absent at the end of a rule, unless absent positions are stored as
+    /// `null`: `""` is then a value.
+    pub(crate) fn decode(&self, document: Value) -> serde_json::Result<CasbinRule> {
+        let text = |key: &str| document.get(key).and_then(Value::as_str).map(str::to_owned);
+        let items = |key: &str| {
+            document
+                .get(key)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .map(|v| v.as_str().map(str::to_owned))
+        };
+        let ptype = text("ptype").ok_or_else(|| Error::missing_field("ptype"))?;
+
+        let mut values: Vec<Option<String>> = match &self.shape {
+            Shape::Columns => (0..COLUMNS)
+                .map(|i| text(&format!("v{}", i)))
+                .chain(items("extra"))
+                .collect(),
+            Shape::Array if document.get("rule").is_some_and(Value::is_array) => {
+                items("rule").collect()
+            }
+            Shape::Array => return Err(Error::missing_field("rule")),
+            Shape::Named(_) => {
+                let width = self.width(&ptype);
+                let mut values = vec![];
+                for i in 0.. {
+                    match document.get(self.field_name(&ptype, i)) {
+                        Some(Value::String(value)) => values.push(Some(value.clone())),
+                        _ if i < width => values.push(None),
+                        _ => break,
+                    }
+                }
+                values
+            }
+        };
+
+        let absent = |value: &Option<String>| match value {
+            Some(value) => value.is_empty() && !self.null_absent,
+            None => true,
+        };
+        while values.last().is_some_and(absent) {
+            values.pop();
+        }
+
+        Ok(CasbinRule {
+            _key: text("_key"),
+            _from: text("_from"),
+            _to: text("_to"),
+            ptype,
+            values: values.into_iter().map(Option::unwrap_or_default).collect(),
+            valid_from: document.get("valid_from").and_then(Value::as_u64),
+            valid_until: document.get("valid_until").and_then(Value::as_u64),
+        })
+    }
+
+    /// The attribute holding the value at `index` of a rule of the ptype,
+    /// ready to follow a `.` in AQL.
+    pub(crate) fn field(&self, ptype: &str, index: usize) -> String {
+        match &self.shape {
+            Shape::Columns if index < COLUMNS => format!("v{}", index),
+            Shape::Columns => format!("extra[{}]", index - COLUMNS),
+            Shape::Array => format!("rule[{}]", index),
+            Shape::Named(_) => format!("`{}`", self.field_name(ptype, index)),
+        }
+    }
+
+    fn field_name(&self, ptype: &str, index: usize) -> String {
+        match &self.shape {
+            Shape::Named(names) => names
+                .get(ptype)
+                .and_then(|fields| fields.get(index))
+                .cloned()
+                .unwrap_or_else(|| format!("v{}", index)),
+            _ => format!("v{}", index),
+        }
+    }
+
+    /// The attributes holding the domain of a grouping rule, its third value,
+    /// ready to follow a `.` in AQL.
+    pub(crate) fn domain_fields(&self) -> Vec<String> {
+        let mut fields = vec![self.field("", DOMAIN)];
+
+        if let Shape::Named(names) = &self.shape {
+            for ptype in names.keys().filter(|ptype| ptype.starts_with('g')) {
+                let field = self.field(ptype, DOMAIN);
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+        }
+
+        fields
+    }
+
+    /// The attributes covered by the unique index. The named layout covers
+    /// the fields of every ptype, absent ones being indexed as `null`.
+    pub(crate) fn index_fields(&self) -> Vec<String> {
+        match &self.shape {
+            Shape::Columns => COLUMN_FIELDS.iter().map(|f| f.to_string()).collect(),
+            Shape::Array => ARRAY_FIELDS.iter().map(|f| f.to_string()).collect(),
+            Shape::Named(names) => {
+                let mut fields = vec!["ptype".to_owned()];
+                for name in names.values().flatten() {
+                    if !fields.contains(name) {
+                        fields.push(name.clone());
+                    }
+                }
+                fields
+            }
+        }
+    }
+
+    /// The search document of an UPSERT matching the values of a rule `r` of
+    /// the ptype.
+    pub(crate) fn upsert_search(&self, ptype: &str) -> String {
+        match &self.shape {
+            Shape::Columns => {
+                "{ ptype: r.ptype, v0: r.v0, v1: r.v1, v2: r.v2, v3: r.v3, v4: r.v4, v5: r.v5, extra: r.extra }".to_owned()
+            }
+            Shape::Array => "{ ptype: r.ptype, rule: r.rule }".to_owned(),
+            Shape::Named(_) => {
+                let fields = std::iter::once("ptype: r.ptype".to_owned())
+                    .chain((0..self.width(ptype)).map(|i| format!("{0}: r.{0}", self.field(ptype, i))))
+                    .collect::<Vec<_>>();
+
+                format!("{{ {} }}", fields.join(", "))
+            }
+        }
+    }
+}
+
+/// Rewrites every rule of `collections` from the `from` layout to the `to`
+/// layout, keeping the document keys.
+pub(crate) async fn migrate<E: AqlExecutor>(
+    e: &E,
+    collections: &[String],
+    from: &Layout,
+    to: &Layout,
+) -> Result<()> {
+    for collection in collections {
+        let rules = e.load_policy(collection, from).await?;
+        e.save_policy(collection, to, rules).await?;
+    }
+
+    Ok(())
+}
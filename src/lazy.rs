@@ -0,0 +1,413 @@
+use crate::filter::LoadedPolicy;
+use crate::{
+    load_policy_line, ArangoFilter, ArangorsAdapter, ArangorsAdapterError, CasbinRule,
+    FieldMatcher, FilterGroup,
+};
+use arangors::uclient::ClientExt;
+use casbin::{CoreApi, EnforceArgs, Enforcer, EventData, Result, TryIntoModel};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError};
+
+const DEFAULT_CAPACITY: usize = 1024;
+const MAX_ROLE_DEPTH: usize = 10;
+
+/// What a slice of the policy is made of, see [`LazyEnforcer`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceScope {
+    /// The rules of a domain: `p` rules whose `dom` token is the domain, and
+    /// grouping rules whose third value is.
+    Domain,
+    /// The rules of a subject: its grouping rules and those of its roles, up
+    /// to 10 levels, and the `p` rules whose first value is the subject or
+    /// one of its roles.
+    Subject,
+}
+
+type Rule = (String, Vec<String>);
+
+/// An [`Enforcer`] loading the policy one slice at a time, the first time a
+/// domain or subject is accessed, instead of loading it whole.
+///
+/// The least recently used slices are unloaded once more than `capacity` are
+/// loaded. Rules written with [`add_policy`](Self::add_policy) and
+/// [`remove_policy`](Self::remove_policy) invalidate the slices they belong
+/// to, which are read again on their next access. Rules changed otherwise,
+/// through the enforcer or by other instances, call for the same from a
+/// watcher callback, with a [`SliceInvalidator`].
+///
+/// The enforcer's model never holds the whole policy, its `save_policy`
+/// fails instead of replacing the stored rules.
+pub struct LazyEnforcer<C: ClientExt> {
+    enforcer: Enforcer,
+    reader: ArangorsAdapter<C>,
+    scope: SliceScope,
+    capacity: usize,
+    slices: HashMap<String, Vec<Rule>>,
+    order: VecDeque<String>,
+    invalidator: SliceInvalidator,
+}
+
+/// Invalidates slices of a [`LazyEnforcer`] from anywhere, such as a watcher
+/// callback: they are read again on their next access.
+#[derive(Clone, Default)]
+pub struct SliceInvalidator {
+    pending: Arc<Mutex<Pending>>,
+}
+
+#[derive(Default)]
+struct Pending {
+    keys: HashSet<String>,
+    all: bool,
+}
+
+impl SliceInvalidator {
+    pub fn invalidate(&self, key: &str) {
+        self.lock().keys.insert(key.to_owned());
+    }
+
+    pub fn invalidate_all(&self) {
+        self.lock().all = true;
+    }
+
+    fn take(&self) -> Pending {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<C: ClientExt + Send + 'static> LazyEnforcer<C> {
+    /// Builds the enforcer from the model, without loading any rule.
+    pub async fn new<M: TryIntoModel>(
+        model: M,
+        adapter: ArangorsAdapter<C>,
+        scope: SliceScope,
+    ) -> Result<Self> {
+        let reader = adapter.reader();
+        // the enforcer must not save its partial model over the stored rules
+        adapter.set_loaded(LoadedPolicy::Filtered(vec![]));
+
+        Ok(Self {
+            enforcer: Enforcer::new_raw(model, adapter).await?,
+            reader,
+            scope,
+            capacity: DEFAULT_CAPACITY,
+            slices: HashMap::new(),
+            order: VecDeque::new(),
+            invalidator: SliceInvalidator::default(),
+        })
+    }
+
+    /// How many slices stay loaded, 1024 by default. The slice in use always
+    /// does.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    pub fn enforcer(&self) -> &Enforcer {
+        &self.enforcer
+    }
+
+    /// The enforcer, to change rules or query the loaded ones.
+    pub fn enforcer_mut(&mut self) -> &mut Enforcer {
+        &mut self.enforcer
+    }
+
+    /// A handle invalidating the slices of this enforcer.
+    pub fn invalidator(&self) -> SliceInvalidator {
+        self.invalidator.clone()
+    }
+
+    /// The loaded slices, from the least to the most recently used.
+    pub fn loaded_slices(&self) -> Vec<String> {
+        self.order.iter().cloned().collect()
+    }
+
+    /// Loads the slice of `key` unless it is loaded, and marks it as the most
+    /// recently used.
+    pub async fn load(&mut self, key: &str) -> Result<()> {
+        self.apply_invalidations()?;
+
+        if self.slices.contains_key(key) {
+            self.order.retain(|k| k != key);
+            self.order.push_back(key.to_owned());
+            return Ok(());
+        }
+
+        let rules = self.fetch(key).await?;
+
+        let model = self.enforcer.get_mut_model();
+        for (ptype, rule) in &rules {
+            model.add_policy(&section(ptype)?, ptype, rule.clone());
+        }
+        self.slices.insert(key.to_owned(), rules);
+        self.order.push_back(key.to_owned());
+
+        while self.order.len() > self.capacity.max(1) {
+            if let Some(oldest) = self.order.pop_front() {
+                self.unload(&oldest);
+            }
+        }
+
+        self.enforcer.build_role_links()
+    }
+
+    /// Loads the slice of `key` if needed, then enforces the request.
+    pub async fn enforce<ARGS: EnforceArgs>(&mut self, key: &str, rvals: ARGS) -> Result<bool> {
+        self.load(key).await?;
+        self.enforcer.enforce(rvals)
+    }
+
+    /// Stores a rule of the ptype, `p` or grouping, and invalidates the loaded
+    /// slices it belongs to. Returns `false` if it was already stored.
+    pub async fn add_policy(&mut self, ptype: &str, rule: Vec<String>) -> Result<bool> {
+        let sec = section(ptype)?;
+        let added = self
+            .enforcer
+            .get_mut_adapter()
+            .add_policy(&sec, ptype, rule.clone())
+            .await?;
+
+        if added {
+            self.written(EventData::AddPolicy(sec, ptype.to_owned(), rule))?;
+        }
+
+        Ok(added)
+    }
+
+    /// Removes a rule of the ptype, `p` or grouping, and invalidates the
+    /// loaded slices it belonged to. Returns `false` if it wasn't stored.
+    pub async fn remove_policy(&mut self, ptype: &str, rule: Vec<String>) -> Result<bool> {
+        let sec = section(ptype)?;
+        let removed = self
+            .enforcer
+            .get_mut_adapter()
+            .remove_policy(&sec, ptype, rule.clone())
+            .await?;
+
+        if removed {
+            self.written(EventData::RemovePolicy(sec, ptype.to_owned(), rule))?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Unloads the slice of `key`, it is read again on its next access.
+    pub fn invalidate(&mut self, key: &str) -> Result<()> {
+        self.order.retain(|k| k != key);
+        if self.unload(key) {
+            self.enforcer.build_role_links()?;
+        }
+
+        Ok(())
+    }
+
+    /// Unloads every slice.
+    pub fn invalidate_all(&mut self) -> Result<()> {
+        self.enforcer.get_mut_model().clear_policy();
+        self.slices.clear();
+        self.order.clear();
+        self.enforcer.build_role_links()
+    }
+
+    /// Invalidates the loaded slices holding the written rule, and notifies
+    /// the enforcer's watcher like the enforcer would.
+    fn written(&mut self, data: EventData) -> Result<()> {
+        let (ptype, rule) = match &data {
+            EventData::AddPolicy(_, ptype, rule) | EventData::RemovePolicy(_, ptype, rule) => {
+                (ptype, rule)
+            }
+            _ => return Ok(()),
+        };
+
+        for key in self.slices_of(ptype, rule) {
+            self.invalidate(&key)?;
+        }
+
+        #[cfg(feature = "watcher")]
+        if self.enforcer.has_auto_notify_watcher_enabled() {
+            if let Some(watcher) = self.enforcer.get_mut_watcher() {
+                watcher.update(data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The loaded slices a rule of the ptype belongs to: that of its domain,
+    /// or every slice if it has none, and the slices of the subjects having
+    /// its first value as themselves or a role.
+    fn slices_of(&self, ptype: &str, rule: &[String]) -> Vec<String> {
+        match self.scope {
+            SliceScope::Domain => match self.domain_index(ptype).and_then(|i| rule.get(i)) {
+                Some(domain) => self
+                    .slices
+                    .keys()
+                    .filter(|k| *k == domain)
+                    .cloned()
+                    .collect(),
+                None => self.slices.keys().cloned().collect(),
+            },
+            SliceScope::Subject => {
+                let subject = rule.first();
+                self.slices
+                    .iter()
+                    .filter(|(key, rules)| {
+                        Some(*key) == subject
+                            || rules.iter().any(|(ptype, rule)| {
+                                ptype.starts_with('g') && rule.get(1) == subject
+                            })
+                    })
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            }
+        }
+    }
+
+    fn apply_invalidations(&mut self) -> Result<()> {
+        let pending = self.invalidator.take();
+
+        if pending.all {
+            return self.invalidate_all();
+        }
+
+        for key in pending.keys {
+            self.invalidate(&key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the rules of the slice from the model, but those other loaded
+    /// slices hold too. The slice is left in the LRU order.
+    fn unload(&mut self, key: &str) -> bool {
+        let rules = match self.slices.remove(key) {
+            Some(rules) => rules,
+            None => return false,
+        };
+
+        let held: HashSet<&Rule> = self.slices.values().flatten().collect();
+        let model = self.enforcer.get_mut_model();
+        // the rules were loaded, so their ptype has a section
+        for (ptype, rule) in rules.iter().filter(|rule| !held.contains(rule)) {
+            if let Ok(sec) = section(ptype) {
+                model.remove_policy(&sec, ptype, rule.clone());
+            }
+        }
+
+        true
+    }
+
+    async fn fetch(&self, key: &str) -> Result<Vec<Rule>> {
+        let rules = match self.scope {
+            SliceScope::Domain => self.reader.grouped_rules(&self.domain_filter(key)).await?,
+            SliceScope::Subject => self.subject_rules(key).await?,
+        };
+
+        Ok(rules
+            .iter()
+            .filter_map(|rule| Some((rule.ptype.clone(), load_policy_line(rule)?)))
+            .collect())
+    }
+
+    fn domain_filter(&self, domain: &str) -> ArangoFilter {
+        let mut filter = ArangoFilter::new();
+
+        for sec in ["p", "g"] {
+            let assertions = self.enforcer.get_model().get_model().get(sec);
+            for ptype in assertions.into_iter().flat_map(|a| a.keys()) {
+                if let Some(index) = self.domain_index(ptype) {
+                    filter = filter.with_group(
+                        FilterGroup::new(ptype)
+                            .with_field(index, FieldMatcher::Exact(domain.to_owned())),
+                    );
+                }
+            }
+        }
+
+        filter
+    }
+
+    /// The position of the domain in the rules of the ptype: that of the
+    /// `dom` token of a `p` rule, the third value of a grouping rule.
+    fn domain_index(&self, ptype: &str) -> Option<usize> {
+        let sec = section(ptype).ok()?;
+        let assertion = self
+            .enforcer
+            .get_model()
+            .get_model()
+            .get(&sec)?
+            .get(ptype)?;
+        let tokens: Vec<&str> = assertion.value.split(',').map(str::trim).collect();
+
+        match sec.as_str() {
+            "p" => tokens.iter().position(|token| *token == "dom"),
+            _ => Some(2).filter(|i| *i < tokens.len()),
+        }
+    }
+
+    /// Follows the grouping rules from the subject, then reads the `p` rules
+    /// of the subject and the roles found.
+    async fn subject_rules(&self, subject: &str) -> Result<Vec<CasbinRule>> {
+        let mut subjects = vec![subject.to_owned()];
+        let mut frontier = subjects.clone();
+        let mut rules = vec![];
+
+        for _ in 0..MAX_ROLE_DEPTH {
+            if frontier.is_empty() {
+                break;
+            }
+
+            let found = self
+                .reader
+                .grouped_rules(&self.first_value_filter("g", &frontier))
+                .await?;
+
+            frontier = vec![];
+            for role in found.iter().map(|rule| rule.value(1)) {
+                if !subjects.iter().any(|s| s == role) {
+                    subjects.push(role.to_owned());
+                    frontier.push(role.to_owned());
+                }
+            }
+            rules.extend(found);
+        }
+
+        rules.extend(
+            self.reader
+                .grouped_rules(&self.first_value_filter("p", &subjects))
+                .await?,
+        );
+
+        Ok(rules)
+    }
+
+    /// The rules of every ptype of the section whose first value is one of
+    /// `names`.
+    fn first_value_filter(&self, sec: &str, names: &[String]) -> ArangoFilter {
+        let assertions = self.enforcer.get_model().get_model().get(sec);
+
+        assertions
+            .into_iter()
+            .flatten()
+            .fold(ArangoFilter::new(), |filter, (ptype, _)| {
+                filter.with_group(
+                    FilterGroup::new(ptype).with_field(0, FieldMatcher::In(names.to_vec())),
+                )
+            })
+    }
+}
+
+/// The section of the ptype, `p` or `g`, named after its first character
+/// like `load_rules` does.
+pub(crate) fn section(ptype: &str) -> Result<String> {
+    ptype.chars().next().map(|x| x.to_string()).ok_or_else(|| {
+        ArangorsAdapterError::InvalidPtype {
+            ptype: ptype.to_owned(),
+        }
+        .into()
+    })
+}
@@ -2,78 +2,1149 @@ use crate::model::CasbinRule;
 use arangors::uclient::ClientExt;
 use arangors::Database;
 use async_trait::async_trait;
+use audit::SharedActor;
 use casbin::{Adapter, Filter, Model};
-use casbin_dao::CasbinDao;
+use casbin_dao::{in_transaction, stream_policy, CasbinDao, Executor, Scope};
+use filter::{LoadedPolicy, SharedLoaded};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use graph::RoleGraph;
+use layout::Layout;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::SystemTime;
+use transaction::SharedTransaction;
+use validity::unix_seconds;
+use versions::{VersionedRule, Versions};
 
+mod audit;
 mod casbin_dao;
+mod error;
+mod filter;
+mod graph;
+mod layout;
+mod lazy;
 mod model;
+#[cfg(feature = "role-manager")]
+mod role_manager;
+mod schema;
+mod transaction;
+mod validity;
+mod versions;
+#[cfg(feature = "watcher")]
+mod watcher;
+
+pub use audit::{AuditEntry, AuditHandle, AuditOperation};
+pub use error::ArangorsAdapterError;
+pub use filter::{ArangoFilter, FieldMatcher, FilterGroup, FilterHandle};
+pub use layout::DocumentLayout;
+pub use lazy::{LazyEnforcer, SliceInvalidator, SliceScope};
+#[cfg(feature = "role-manager")]
+pub use role_manager::ArangorsRoleManager;
+pub use transaction::TransactionHandle;
+pub use validity::Validity;
+pub use versions::{PolicyDiff, PolicyVersion};
+#[cfg(feature = "watcher")]
+pub use watcher::ArangorsWatcher;
 
 #[cfg(test)]
 mod lib_test;
 
+const DEFAULT_COLLECTION: &str = "casbin";
+const DEFAULT_BATCH_SIZE: u32 = 1000;
+
+/// The result of a change, and the rules to record in the audit collection.
+type Audited<R> = (R, Option<Vec<(String, Vec<String>)>>);
+
 pub struct ArangorsAdapter<C: ClientExt> {
     database: Database<C>,
-    is_filtered: bool,
+    collection: String,
+    loaded: SharedLoaded,
+    idempotent_add: bool,
+    deterministic_keys: bool,
+    layout: Layout,
+    role_graph: Option<RoleGraph>,
+    batch_size: u32,
+    audit: Option<String>,
+    audit_actor: SharedActor,
+    versions: Option<Versions>,
+    ttl_index: bool,
+    transaction: SharedTransaction<C>,
 }
 
 impl<C: ClientExt> ArangorsAdapter<C> {
+    /// Creates an adapter storing its rules in the `casbin` collection.
     pub fn new(database: Database<C>) -> Self {
+        Self::new_with_collection(database, DEFAULT_COLLECTION)
+    }
+
+    /// Creates an adapter storing its rules in the given collection, so that
+    /// several applications or environments can share one database.
+    pub fn new_with_collection(database: Database<C>, collection: &str) -> Self {
         Self {
             database,
-            is_filtered: false,
+            collection: collection.to_owned(),
+            loaded: Arc::new(RwLock::new(LoadedPolicy::Nothing)),
+            idempotent_add: false,
+            deterministic_keys: false,
+            layout: Layout::default(),
+            role_graph: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            audit: None,
+            audit_actor: Arc::new(RwLock::new(None)),
+            versions: None,
+            ttl_index: false,
+            transaction: Arc::new(RwLock::new(None)),
         }
     }
+
+    /// Creates an adapter on the given collection and bootstraps its schema,
+    /// see [`ArangorsAdapter::ensure_schema`].
+    pub async fn new_with_setup(database: Database<C>, collection: &str) -> casbin::Result<Self> {
+        let adapter = Self::new_with_collection(database, collection);
+        adapter.ensure_schema().await?;
+        Ok(adapter)
+    }
+
+    /// When enabled, `add_policy` and `add_policies` skip rules that are
+    /// already stored instead of failing on the unique index, and return
+    /// `false` when nothing was inserted. This makes retries safe.
+    pub fn with_idempotent_add(mut self, enabled: bool) -> Self {
+        self.idempotent_add = enabled;
+        self
+    }
+
+    /// When enabled, every stored rule gets a `_key` derived from its content,
+    /// so the primary index rejects duplicates and removals are key lookups.
+    /// Rules stored before enabling it keep their random keys until the next
+    /// `save_policy`.
+    pub fn with_deterministic_keys(mut self, enabled: bool) -> Self {
+        self.deterministic_keys = enabled;
+        self
+    }
+
+    /// Selects how rules are laid out in their documents, see
+    /// [`DocumentLayout`]. Rules stored with another layout can be converted
+    /// with [`ArangorsAdapter::migrate_layout`].
+    pub fn with_layout(mut self, layout: DocumentLayout) -> Self {
+        self.layout.shape = Layout::from(layout).shape;
+        self
+    }
+
+    /// Stores one property per value named after the tokens of the model's
+    /// `[policy_definition]` and `[role_definition]`, e.g.
+    /// `{ptype: "p", sub: "alice", obj: "data1", act: "read"}`, so the
+    /// collection is readable from other applications. Role definitions have
+    /// no names, their values are stored as `user`, `role` and `domain`.
+    pub fn with_named_fields(mut self, m: &dyn Model) -> Self {
+        self.layout.shape = Layout::named(m).shape;
+        self
+    }
+
+    /// When enabled, positions past the last value of a rule are stored as
+    /// `null` instead of `""`, so that empty values, trailing ones included,
+    /// are kept: `["alice", "data1", ""]` no longer loads as
+    /// `["alice", "data1"]`. Rules stored before enabling it must be
+    /// converted with `migrate_layout(DocumentLayout::Columns)`:
+    /// [`ensure_schema`](Self::ensure_schema) fails with an
+    /// [`ArangorsAdapterError::PaddedRules`] while any is left.
+    pub fn with_null_absent(mut self, enabled: bool) -> Self {
+        self.layout.null_absent = enabled;
+        self
+    }
+
+    /// Stores grouping rules (`g`, `g2`, ...) as edges of the named graph
+    /// `graph` instead of documents of the policy collection. Subjects, roles
+    /// and domains become vertices of `{graph}_vertices`, rules edges of
+    /// `{graph}_edges`, so the role hierarchy can be traversed with AQL.
+    /// Grouping rules stored before enabling it are moved by the next
+    /// `save_policy`.
+    pub fn with_role_graph(mut self, graph: &str) -> Self {
+        self.role_graph = Some(RoleGraph::new(graph));
+        self
+    }
+
+    /// How many rules `load_policy` and `stream_policies` fetch from the
+    /// cursor per round trip, 1000 by default.
+    pub fn with_batch_size(mut self, batch_size: u32) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Records every change in the collection `collection`, in the same
+    /// transaction as the change: its operation, the rules added or removed,
+    /// the time, and the actor set through [`ArangorsAdapter::audit_handle`].
+    pub fn with_audit(mut self, collection: &str) -> Self {
+        self.audit = Some(collection.to_owned());
+        self
+    }
+
+    /// Returns a handle to tell who makes the next changes, see
+    /// [`AuditHandle`].
+    pub fn audit_handle(&self) -> AuditHandle {
+        AuditHandle::new(self.audit_actor.clone())
+    }
+
+    /// Keeps snapshots of the policy taken with
+    /// [`ArangorsAdapter::snapshot`]: a document per version in the collection
+    /// `name`, and their rules in `{name}_rules`.
+    pub fn with_versions(mut self, name: &str) -> Self {
+        self.versions = Some(Versions::new(name));
+        self
+    }
+
+    /// When enabled, [`ArangorsAdapter::ensure_schema`] installs a TTL index
+    /// on `valid_until`, so that ArangoDB deletes the rules added with
+    /// [`ArangorsAdapter::add_policy_with_validity`] once they expire. The
+    /// collection can only have one TTL index, none is added if it already
+    /// has one.
+    pub fn with_ttl_index(mut self, enabled: bool) -> Self {
+        self.ttl_index = enabled;
+        self
+    }
+
+    pub fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    /// Returns the `_id` (`collection/_key`) the rule is stored under, so that
+    /// other collections can refer to it. `None` unless deterministic keys are
+    /// enabled, as the keys are generated by ArangoDB otherwise.
+    pub fn rule_id(&self, ptype: &str, rule: &[String]) -> Option<String> {
+        if !self.deterministic_keys {
+            return None;
+        }
+
+        self.layout
+            .rule(ptype, rule)
+            .map(|rule| format!("{}/{}", self.rules_collection(ptype), rule.content_key()))
+    }
+
+    /// Creates the policy collection if it is missing and installs the
+    /// persistent unique index on the rule attributes, `ptype,v0..v5,extra`
+    /// or `ptype,rule` depending on the layout. Fails with an
+    /// [`ArangorsAdapterError::IndexMismatch`] if an index with the adapter's
+    /// name exists but covers other fields, or if another unique index covers
+    /// `ptype` and rule attributes other than exactly those, and with an
+    /// [`ArangorsAdapterError::PaddedRules`] if absent positions are stored as
+    /// `null` but rules padded with `""` are left. The collections of the role
+    /// graph, audit and versions, and the TTL index, are set up when enabled.
+    pub async fn ensure_schema(&self) -> casbin::Result<()> {
+        schema::ensure_schema(&self.database, &self.collection, &self.layout).await?;
+
+        if let Some(graph) = &self.role_graph {
+            graph::ensure_graph(&self.database, graph, &self.layout).await?;
+        }
+
+        for collection in self.rule_collections() {
+            schema::ensure_no_padded_rules(&self.database, &collection, &self.layout).await?;
+        }
+
+        if self.ttl_index {
+            schema::ensure_ttl_index(&self.database, &self.collection).await?;
+
+            if let Some(graph) = &self.role_graph {
+                schema::ensure_ttl_index(&self.database, &graph.edges).await?;
+            }
+        }
+
+        if let Some(audit) = &self.audit {
+            schema::ensure_audit_schema(&self.database, audit).await?;
+        }
+
+        if let Some(versions) = &self.versions {
+            schema::ensure_versions_schema(&self.database, versions).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Every collection the adapter writes to.
+    fn collections(&self) -> Vec<String> {
+        let mut collections = vec![self.collection.clone()];
+
+        if let Some(graph) = &self.role_graph {
+            collections.push(graph.edges.clone());
+            collections.push(graph.vertices.clone());
+        }
+
+        if let Some(audit) = &self.audit {
+            collections.push(audit.clone());
+        }
+
+        if let Some(versions) = &self.versions {
+            collections.push(versions.versions.clone());
+            collections.push(versions.rules.clone());
+        }
+
+        collections
+    }
+
+    /// The collections holding rules: the policy collection and the edges of
+    /// the role graph.
+    fn rule_collections(&self) -> Vec<String> {
+        let mut collections = vec![self.collection.clone()];
+        if let Some(graph) = &self.role_graph {
+            collections.push(graph.edges.clone());
+        }
+
+        collections
+    }
+
+    /// The role graph, if rules of this ptype are stored as its edges.
+    fn edge_graph(&self, ptype: &str) -> Option<&RoleGraph> {
+        self.role_graph.as_ref().filter(|_| ptype.starts_with('g'))
+    }
+
+    fn rules_collection(&self, ptype: &str) -> &str {
+        match self.edge_graph(ptype) {
+            Some(graph) => &graph.edges,
+            None => &self.collection,
+        }
+    }
+
+    /// Rules of the ptype as the audit collection records them, like they are
+    /// stored.
+    fn audit_rules(&self, ptype: &str, rules: &[Vec<String>]) -> Vec<(String, Vec<String>)> {
+        rules
+            .iter()
+            .map(|rule| (ptype.to_owned(), self.layout.stored_values(rule.clone())))
+            .collect()
+    }
+
+    fn to_casbin_rule(&self, ptype: &str, rule: &[String]) -> Option<CasbinRule> {
+        let mut new_rule = self.layout.rule(ptype, rule)?;
+
+        if self.deterministic_keys {
+            new_rule._key = Some(new_rule.content_key());
+        }
+
+        Some(new_rule)
+    }
 }
 
-#[async_trait]
-impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
-    async fn load_policy(&self, m: &mut dyn Model) -> casbin::Result<()> {
-        let rules = self.database.load_policy().await?;
+impl<C: ClientExt + Send> ArangorsAdapter<C> {
+    /// Returns a handle to group several adapter calls into one stream
+    /// transaction, see [`TransactionHandle`].
+    pub fn transaction_handle(&self) -> TransactionHandle<C> {
+        TransactionHandle::new(
+            self.database.clone(),
+            self.collections(),
+            self.transaction.clone(),
+        )
+    }
 
-        for casbin_rule in &rules {
-            let rule = load_policy_line(casbin_rule);
+    /// Returns a handle to load more filters into the model of the enforcer
+    /// the adapter is given to, see [`FilterHandle`].
+    pub fn filter_handle(&self) -> FilterHandle<C> {
+        FilterHandle::new(Self {
+            loaded: self.loaded.clone(),
+            ..self.reader()
+        })
+    }
 
-            if let Some(ref sec) = casbin_rule.ptype.chars().next().map(|x| x.to_string()) {
-                if let Some(t1) = m.get_mut_model().get_mut(sec) {
-                    if let Some(t2) = t1.get_mut(&casbin_rule.ptype) {
-                        if let Some(rule) = rule {
-                            t2.get_mut_policy().insert(rule);
-                        }
+    /// Removes the rules of the ptype in one query and returns them as they
+    /// were stored. If any of them isn't stored, nothing is removed and `None`
+    /// is returned, which `remove_policies` reports as `false`.
+    pub async fn remove_policies_returning(
+        &self,
+        ptype: &str,
+        rules: Vec<Vec<String>>,
+    ) -> casbin::Result<Option<Vec<Vec<String>>>> {
+        self.audited(AuditOperation::RemovePolicies, |e| async move {
+            let collection = self.rules_collection(ptype);
+
+            let removed = if self.deterministic_keys {
+                let keys = rules
+                    .iter()
+                    .map(|rule| self.to_casbin_rule(ptype, rule).map(|r| r.content_key()))
+                    .collect::<Option<Vec<String>>>();
+
+                match keys {
+                    Some(keys) => {
+                        e.remove_policies_by_key(collection, &self.layout, keys)
+                            .await?
                     }
+                    None => None,
                 }
+            } else {
+                e.remove_policies(collection, &self.layout, ptype, rules)
+                    .await?
+            };
+
+            let removed: Option<Vec<Vec<String>>> =
+                removed.map(|rules| rules.iter().filter_map(load_policy_line).collect());
+            if let Some(removed) = &removed {
+                self.remove_orphans(&e, ptype, removed).await?;
             }
+            let audited = removed.as_ref().map(|rules| self.audit_rules(ptype, rules));
+            Ok((removed, audited))
+        })
+        .await
+    }
+
+    /// Removes the rules of the ptype whose values from `field_index` on match
+    /// the non-empty `field_values`, and returns them as they were stored.
+    /// Nothing is removed without field values.
+    pub async fn remove_filtered_policy_returning(
+        &self,
+        ptype: &str,
+        field_index: usize,
+        field_values: Vec<String>,
+    ) -> casbin::Result<Vec<Vec<String>>> {
+        if field_values.is_empty() {
+            return Ok(vec![]);
         }
 
+        self.audited(AuditOperation::RemoveFilteredPolicy, |e| async move {
+            let removed = e
+                .remove_filtered_policy(
+                    self.rules_collection(ptype),
+                    &self.layout,
+                    ptype,
+                    field_index,
+                    field_values,
+                )
+                .await?;
+
+            let removed: Vec<Vec<String>> = removed.iter().filter_map(load_policy_line).collect();
+            self.remove_orphans(&e, ptype, &removed).await?;
+            let audited = Some(self.audit_rules(ptype, &removed)).filter(|rules| !rules.is_empty());
+            Ok((removed, audited))
+        })
+        .await
+    }
+
+    /// Like `remove_filtered_policy_returning`, with a [`FieldMatcher`] per
+    /// value from `field_index` on instead of exact values, to remove e.g.
+    /// every rule on `Glob("/projects/42/*")`. With `dry_run` the rules are
+    /// returned without being removed. The enforcer's model is left as is,
+    /// reload it afterwards. Nothing is removed without matchers.
+    pub async fn remove_matching_policy(
+        &self,
+        ptype: &str,
+        field_index: usize,
+        matchers: Vec<FieldMatcher>,
+        dry_run: bool,
+    ) -> casbin::Result<Vec<Vec<String>>> {
+        if matchers.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.audited(AuditOperation::RemoveMatchingPolicy, |e| async move {
+            let removed = e
+                .remove_matching_policy(
+                    self.rules_collection(ptype),
+                    &self.layout,
+                    ptype,
+                    field_index,
+                    &matchers,
+                    dry_run,
+                )
+                .await?;
+
+            let removed: Vec<Vec<String>> = removed.iter().filter_map(load_policy_line).collect();
+            if !dry_run {
+                self.remove_orphans(&e, ptype, &removed).await?;
+            }
+            let audited = Some(self.audit_rules(ptype, &removed))
+                .filter(|rules| !dry_run && !rules.is_empty());
+            Ok((removed, audited))
+        })
+        .await
+    }
+
+    /// Like `add_policy`, for a rule that only applies within `validity`:
+    /// outside of it the rule is stored but not loaded. An expired copy of
+    /// the rule still waiting for the TTL index is replaced. The enforcer's
+    /// model is left as is, reload it afterwards. Until its window starts,
+    /// the rule can only be removed with
+    /// [`revoke_policy`](Self::revoke_policy).
+    pub async fn add_policy_with_validity(
+        &self,
+        ptype: &str,
+        rule: Vec<String>,
+        validity: Validity,
+    ) -> casbin::Result<bool> {
+        self.audited(AuditOperation::AddPolicy, |e| async move {
+            let inserted = self
+                .atomic(e, |e| async move {
+                    self.remove_expired(&e, ptype).await?;
+                    self.insert_policy(&e, ptype, rule, validity).await
+                })
+                .await?;
+            Ok(added(&inserted))
+        })
+        .await
+    }
+
+    /// Removes the stored rule whatever its window, and returns whether it was
+    /// stored. A rule whose window hasn't started isn't in the enforcer's
+    /// model, so `Enforcer::remove_policy` reports it as missing and notifies
+    /// no watcher, and `save_policy` keeps it: revoke it with this method,
+    /// from any adapter on the same collection.
+    pub async fn revoke_policy(&self, ptype: &str, rule: Vec<String>) -> casbin::Result<bool> {
+        let removed = self.audit_rules(ptype, std::slice::from_ref(&rule));
+        self.audited(AuditOperation::RemovePolicy, |e| async move {
+            let deleted = self.delete_policy(&e, ptype, rule).await?;
+            Ok((deleted, Some(removed).filter(|_| deleted)))
+        })
+        .await
+    }
+
+    /// Like `add_policies`, for rules that only apply within `validity`, see
+    /// [`ArangorsAdapter::add_policy_with_validity`].
+    pub async fn add_policies_with_validity(
+        &self,
+        ptype: &str,
+        rules: Vec<Vec<String>>,
+        validity: Validity,
+    ) -> casbin::Result<bool> {
+        self.audited(AuditOperation::AddPolicies, |e| async move {
+            let inserted = self
+                .atomic(e, |e| async move {
+                    self.remove_expired(&e, ptype).await?;
+                    self.insert_policies(&e, ptype, rules, validity).await
+                })
+                .await?;
+            Ok(added(&inserted))
+        })
+        .await
+    }
+
+    /// The audit entries of the rule, oldest first. Fails with
+    /// [`ArangorsAdapterError::AuditNotEnabled`] without `with_audit`.
+    pub async fn audit_history(
+        &self,
+        ptype: &str,
+        rule: &[String],
+    ) -> casbin::Result<Vec<AuditEntry>> {
+        let rule = self.layout.stored_values(rule.to_vec());
+        audit::rule_history(&self.executor(), self.audit_collection()?, ptype, &rule).await
+    }
+
+    /// The audit entries of the rules whose first value is `subject`, such as
+    /// the policies and roles granted to a user, oldest first.
+    pub async fn audit_history_for_subject(
+        &self,
+        subject: &str,
+    ) -> casbin::Result<Vec<AuditEntry>> {
+        audit::subject_history(&self.executor(), self.audit_collection()?, subject).await
+    }
+
+    fn audit_collection(&self) -> casbin::Result<&str> {
+        self.audit
+            .as_deref()
+            .ok_or_else(|| ArangorsAdapterError::AuditNotEnabled.into())
+    }
+
+    /// Stores the current rules, those of the role graph and those outside
+    /// their validity window included, as a new version labelled `label`,
+    /// read in one transaction.
+    pub async fn snapshot(&self, label: &str) -> casbin::Result<PolicyVersion> {
+        let versions = self.versions()?;
+
+        self.atomic(self.executor(), |e| async move {
+            // every stored rule, whether its window is open or not
+            let mut rules = e.load_policy(&self.collection, &self.layout).await?;
+            if let Some(graph) = &self.role_graph {
+                rules.extend(e.load_policy(&graph.edges, &self.layout).await?);
+            }
+
+            let rules: Vec<VersionedRule> = rules
+                .into_iter()
+                .filter_map(|rule| {
+                    Some(VersionedRule {
+                        rule: load_policy_line(&rule)?,
+                        ptype: rule.ptype,
+                        valid_from: rule.valid_from,
+                        valid_until: rule.valid_until,
+                    })
+                })
+                .collect();
+
+            versions.create(&e, label, &rules).await
+        })
+        .await
+    }
+
+    /// The versions taken with `snapshot`, oldest first.
+    pub async fn list_versions(&self) -> casbin::Result<Vec<PolicyVersion>> {
+        self.versions()?.list(&self.executor()).await
+    }
+
+    /// The rules added and removed from the version `from` to `to`.
+    pub async fn diff_versions(&self, from: &str, to: &str) -> casbin::Result<PolicyDiff> {
+        self.versions()?.diff(&self.executor(), from, to).await
+    }
+
+    /// Replaces the stored rules with those of the version in one
+    /// transaction, like `save_policy`, with the validity windows they had.
+    /// The enforcer's model is left as is, reload it afterwards.
+    pub async fn rollback(&self, version: &str) -> casbin::Result<()> {
+        let versions = self.versions()?;
+
+        self.audited(AuditOperation::RollbackPolicy, |e| async move {
+            let restored = versions.rules(&e, version).await?;
+            let rules = restored
+                .iter()
+                .filter_map(|r| {
+                    Some(CasbinRule {
+                        valid_from: r.valid_from,
+                        valid_until: r.valid_until,
+                        ..self.to_casbin_rule(&r.ptype, &r.rule)?
+                    })
+                })
+                .collect();
+
+            self.replace_policy(&e, rules).await?;
+            let restored = restored.into_iter().map(|r| (r.ptype, r.rule)).collect();
+            Ok(((), Some(restored)))
+        })
+        .await
+    }
+
+    fn versions(&self) -> casbin::Result<&Versions> {
+        self.versions
+            .as_ref()
+            .ok_or_else(|| ArangorsAdapterError::VersionsNotEnabled.into())
+    }
+
+    /// Replaces the policy of the model with the rules matching the filter,
+    /// like `load_filtered_policy` with an [`ArangoFilter`] instead of a
+    /// `casbin::Filter`. Build the enforcer from the model with
+    /// `Enforcer::new_raw`, `Enforcer::new` would load the whole policy.
+    pub async fn load_filtered_policy_with(
+        &mut self,
+        m: &mut dyn Model,
+        filter: &ArangoFilter,
+    ) -> casbin::Result<()> {
+        let rules = self.grouped_rules(filter).await?;
+
+        m.clear_policy();
+        load_rules(m, &rules);
+        self.set_loaded(LoadedPolicy::Filtered(vec![filter.clone()]));
+
         Ok(())
     }
 
-    async fn load_filtered_policy<'a>(
+    /// Adds the rules matching the filter to the policy already loaded in the
+    /// model, keeping the rules of the previous filters, and returns whether
+    /// the filter was loaded. A filter loaded before is skipped, and so is
+    /// any filter once the whole policy is loaded. Once the adapter is in an
+    /// `Enforcer`, load through a [`FilterHandle`] instead.
+    pub async fn load_incremental_filtered_policy(
         &mut self,
         m: &mut dyn Model,
-        f: Filter<'a>,
+        filter: &ArangoFilter,
+    ) -> casbin::Result<bool> {
+        self.load_incremental(m, filter).await
+    }
+
+    async fn load_incremental(
+        &self,
+        m: &mut dyn Model,
+        filter: &ArangoFilter,
+    ) -> casbin::Result<bool> {
+        let mut filters = match self.loaded() {
+            LoadedPolicy::Nothing => vec![],
+            LoadedPolicy::Whole => return Ok(false),
+            LoadedPolicy::Filtered(filters) if filters.contains(filter) => return Ok(false),
+            LoadedPolicy::Filtered(filters) => filters,
+        };
+
+        let rules = self.grouped_rules(filter).await?;
+
+        load_rules(m, &rules);
+        filters.push(filter.clone());
+        self.set_loaded(LoadedPolicy::Filtered(filters));
+
+        Ok(true)
+    }
+
+    /// The filters the policy of the model was loaded with, in order, or none
+    /// if it was loaded whole. Filters given to `load_filtered_policy` are
+    /// listed as the equivalent [`ArangoFilter`].
+    pub fn loaded_filters(&self) -> Vec<ArangoFilter> {
+        match self.loaded() {
+            LoadedPolicy::Filtered(filters) => filters,
+            _ => vec![],
+        }
+    }
+
+    /// The rules of the policy collection and of the role graph matching the
+    /// filter.
+    async fn grouped_rules(&self, filter: &ArangoFilter) -> casbin::Result<Vec<CasbinRule>> {
+        let (edge_groups, groups): (Vec<&FilterGroup>, Vec<&FilterGroup>) = filter
+            .groups()
+            .iter()
+            .partition(|group| self.edge_graph(group.ptype()).is_some());
+
+        let mut rules = self
+            .executor()
+            .load_grouped_policy(&self.collection, &self.layout, &groups)
+            .await?;
+
+        if let Some(graph) = &self.role_graph {
+            rules.extend(
+                self.executor()
+                    .load_grouped_policy(&graph.edges, &self.layout, &edge_groups)
+                    .await?,
+            );
+        }
+
+        Ok(rules)
+    }
+
+    fn loaded(&self) -> LoadedPolicy {
+        self.loaded
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set_loaded(&self, loaded: LoadedPolicy) {
+        *self.loaded.write().unwrap_or_else(PoisonError::into_inner) = loaded;
+    }
+
+    /// Streams the stored rules as `(ptype, values)` pairs, fetched from a
+    /// cursor in batches of `with_batch_size`, without building a model.
+    pub fn stream_policies(
+        &self,
+    ) -> impl Stream<Item = casbin::Result<(String, Vec<String>)>> + Send + '_ {
+        self.policy_batches()
+            .map_ok(|rules| {
+                stream::iter(rules.into_iter().filter_map(|rule| {
+                    let values = load_policy_line(&rule)?;
+                    Some(Ok((rule.ptype, values)))
+                }))
+            })
+            .try_flatten()
+    }
+
+    /// The rules of the policy collection, then the edges of the role graph,
+    /// one cursor batch at a time.
+    fn policy_batches(&self) -> impl Stream<Item = casbin::Result<Vec<CasbinRule>>> + Send + '_ {
+        let collections = std::iter::once(self.collection.clone())
+            .chain(self.role_graph.as_ref().map(|graph| graph.edges.clone()));
+
+        stream::iter(collections).flat_map(move |collection| {
+            stream_policy(self.executor(), collection, &self.layout, self.batch_size)
+        })
+    }
+
+    /// Converts the stored rules from the `from` layout, padded with `""`, to
+    /// the adapter's layout in one transaction, keeping their keys, and
+    /// rebuilds the unique index to match. Meant to run once, while no other
+    /// instance writes. If the conversion fails, the rules and their index are
+    /// left as they were.
+    pub async fn migrate_layout(&self, from: DocumentLayout) -> casbin::Result<()> {
+        let from = Layout::from(from);
+        if from == self.layout {
+            return Ok(());
+        }
+
+        let collections = self.rule_collections();
+
+        if let Err(e) = self.convert_layout(&collections, &from).await {
+            // the rules are left in the old layout, and so is their index
+            for collection in &collections {
+                if let Err(error) =
+                    schema::ensure_unique_index(&self.database, collection, &from).await
+                {
+                    log::error!(
+                        "cannot restore the unique index of `{}`: {}",
+                        collection,
+                        error
+                    );
+                }
+            }
+
+            return Err(e);
+        }
+
+        self.ensure_schema().await
+    }
+
+    /// Drops the unique indexes of the collections, which would reject every
+    /// rule in the new layout, and rewrites their rules in one transaction.
+    async fn convert_layout(&self, collections: &[String], from: &Layout) -> casbin::Result<()> {
+        for collection in collections {
+            schema::drop_unique_index(&self.database, collection, from).await?;
+        }
+
+        in_transaction(&self.executor(), collections.to_vec(), |e| async move {
+            layout::migrate(&e, collections, from, &self.layout).await
+        })
+        .await
+    }
+
+    /// Stores grouping rules as edges of the role graph, along with their
+    /// vertices, and returns those inserted.
+    async fn add_edges(
+        &self,
+        e: &Executor<'_, C>,
+        graph: &RoleGraph,
+        rules: Vec<CasbinRule>,
+    ) -> casbin::Result<Vec<CasbinRule>> {
+        let edges = rules.into_iter().map(|r| graph.to_edge(r)).collect();
+        let upsert = Some(self.deterministic_keys).filter(|_| self.idempotent_add);
+
+        let collections = vec![graph.edges.clone(), graph.vertices.clone()];
+        in_transaction(e, collections, |e| async move {
+            graph::insert_edges(&e, graph, &self.layout, edges, upsert).await
+        })
+        .await
+    }
+
+    /// An adapter reading the same rules within the same transactions, for
+    /// the queries of a [`LazyEnforcer`].
+    fn reader(&self) -> Self {
+        Self {
+            database: self.database.clone(),
+            collection: self.collection.clone(),
+            loaded: Arc::new(RwLock::new(LoadedPolicy::Nothing)),
+            idempotent_add: self.idempotent_add,
+            deterministic_keys: self.deterministic_keys,
+            layout: self.layout.clone(),
+            role_graph: self.role_graph.clone(),
+            batch_size: self.batch_size,
+            audit: self.audit.clone(),
+            audit_actor: self.audit_actor.clone(),
+            versions: self.versions.clone(),
+            ttl_index: self.ttl_index,
+            transaction: self.transaction.clone(),
+        }
+    }
+
+    /// Runs the change in the transaction `e` runs in, if any, else in one
+    /// begun for it. That one is only known to the change, so neither a
+    /// [`TransactionHandle`] nor other calls can see or end it.
+    async fn atomic<'a, R, F, Fut>(&'a self, e: Executor<'a, C>, change: F) -> casbin::Result<R>
+    where
+        R: Send,
+        F: FnOnce(Executor<'a, C>) -> Fut + Send,
+        Fut: Future<Output = casbin::Result<R>> + Send,
+    {
+        in_transaction(&e, self.collections(), |scope| match scope {
+            Scope::Outer(e) => change(e.clone()),
+            Scope::Transaction(tx) => change(Executor::Transaction(tx)),
+        })
+        .await
+    }
+
+    /// Runs a change and records the rules it reports in the audit
+    /// collection, if enabled, in the same transaction. Nothing is recorded
+    /// when it reports `None`. With a role graph, the change runs in a
+    /// transaction too, as edges and their vertices change together.
+    async fn audited<'a, R, F, Fut>(
+        &'a self,
+        operation: AuditOperation,
+        change: F,
+    ) -> casbin::Result<R>
+    where
+        R: Send,
+        F: FnOnce(Executor<'a, C>) -> Fut + Send,
+        Fut: Future<Output = casbin::Result<Audited<R>>> + Send,
+    {
+        if self.audit.is_none() && self.role_graph.is_none() {
+            return change(self.executor()).await.map(|(r, _)| r);
+        }
+
+        self.atomic(self.executor(), |e| async move {
+            let (r, rules) = change(e.clone()).await?;
+            if let (Some(audit), Some(rules)) = (&self.audit, rules) {
+                audit::record(&e, audit, operation, &self.audit_actor, &rules).await?;
+            }
+            Ok(r)
+        })
+        .await
+    }
+
+    /// Replaces the stored rules, and the edges and vertices of the role
+    /// graph.
+    async fn replace_policy(
+        &self,
+        e: &Executor<'_, C>,
+        rules: Vec<CasbinRule>,
     ) -> casbin::Result<()> {
-        let rules = self
-            .database
-            .load_policy()
-            .await
-            .map_err(|e| casbin::error::AdapterError(Box::new(e)))?;
-
-        for casbin_rule in &rules {
-            let rule = load_filtered_policy_line(casbin_rule, &f);
-
-            if let Some((is_filtered, rule)) = rule {
-                if is_filtered {
-                    self.is_filtered = is_filtered;
-                    if let Some(ref sec) = casbin_rule.ptype.chars().next().map(|x| x.to_string()) {
-                        if let Some(t1) = m.get_mut_model().get_mut(sec) {
-                            if let Some(t2) = t1.get_mut(&casbin_rule.ptype) {
-                                t2.get_mut_policy().insert(rule);
-                            }
-                        }
-                    }
+        if let Some(graph) = &self.role_graph {
+            let (edges, rules): (Vec<_>, Vec<_>) = rules
+                .into_iter()
+                .partition(|r| self.edge_graph(&r.ptype).is_some());
+            let edges = edges.into_iter().map(|r| graph.to_edge(r)).collect();
+
+            return in_transaction(e, self.collections(), |e| async move {
+                graph::replace_all(&e, &self.collection, graph, &self.layout, rules, edges).await
+            })
+            .await;
+        }
+
+        e.save_policy(&self.collection, &self.layout, rules).await
+    }
+
+    /// Gives the rules the validity windows they are stored with, and adds
+    /// the stored rules whose window hasn't started yet, which no model holds,
+    /// so that replacing the policy never makes temporary access permanent.
+    async fn keep_windows(
+        &self,
+        e: &Executor<'_, C>,
+        rules: Vec<CasbinRule>,
+    ) -> casbin::Result<Vec<CasbinRule>> {
+        let mut windowed = e
+            .load_windowed_policy(&self.collection, &self.layout)
+            .await?;
+
+        if let Some(graph) = &self.role_graph {
+            windowed.extend(e.load_windowed_policy(&graph.edges, &self.layout).await?);
+        }
+
+        if windowed.is_empty() {
+            return Ok(rules);
+        }
+
+        let now = unix_seconds(SystemTime::now());
+        // an expired window doesn't apply to a rule added again since
+        let mut windows: HashMap<String, CasbinRule> = windowed
+            .into_iter()
+            .filter(|r| r.valid_until.is_none_or(|until| until > now))
+            .map(|r| (r.content_key(), r))
+            .collect();
+
+        let mut rules: Vec<CasbinRule> = rules
+            .into_iter()
+            .map(|mut rule| {
+                if let Some(window) = windows.remove(&rule.content_key()) {
+                    rule.valid_from = window.valid_from;
+                    rule.valid_until = window.valid_until;
                 }
+                rule
+            })
+            .collect();
+
+        rules.extend(
+            windows
+                .into_values()
+                .filter(|r| r.valid_from.is_some_and(|from| from > now)),
+        );
+
+        Ok(rules)
+    }
+
+    /// Removes the expired rules of the ptype still waiting for the TTL
+    /// index, if any, which would keep the same rule from being added again.
+    /// Every add runs it first, in the same transaction.
+    async fn remove_expired(&self, e: &Executor<'_, C>, ptype: &str) -> casbin::Result<()> {
+        e.remove_expired_policy(self.rules_collection(ptype)).await
+    }
+
+    async fn clear_rules(&self, e: &Executor<'_, C>) -> casbin::Result<()> {
+        if let Some(graph) = &self.role_graph {
+            return in_transaction(e, self.collections(), |e| async move {
+                graph::clear_all(&e, &self.collection, graph).await
+            })
+            .await;
+        }
+
+        e.clear_policy(&self.collection).await
+    }
+
+    /// Stores the rule and returns it, unless it was already stored and
+    /// skipped.
+    async fn insert_policy(
+        &self,
+        e: &Executor<'_, C>,
+        ptype: &str,
+        rule: Vec<String>,
+        validity: Validity,
+    ) -> casbin::Result<Vec<CasbinRule>> {
+        let ptype_c = ptype.to_string();
+
+        if let Some(mut new_rule) = self.to_casbin_rule(&ptype_c, &rule) {
+            (new_rule.valid_from, new_rule.valid_until) = validity.bounds();
+
+            if let Some(graph) = self.edge_graph(&ptype_c) {
+                return self.add_edges(e, graph, vec![new_rule]).await;
             }
+
+            if self.idempotent_add {
+                return e
+                    .upsert_policies(
+                        &self.collection,
+                        &self.layout,
+                        vec![new_rule],
+                        self.deterministic_keys,
+                    )
+                    .await;
+            }
+            e.add_policy(&self.collection, &self.layout, new_rule.clone())
+                .await?;
+            return Ok(vec![new_rule]);
         }
+        Ok(vec![])
+    }
+
+    /// Stores the rules and returns them, but for those already stored and
+    /// skipped.
+    async fn insert_policies(
+        &self,
+        e: &Executor<'_, C>,
+        ptype: &str,
+        rules: Vec<Vec<String>>,
+        validity: Validity,
+    ) -> casbin::Result<Vec<CasbinRule>> {
+        let ptype_c = ptype.to_string();
+        let (valid_from, valid_until) = validity.bounds();
+
+        let new_rules = rules
+            .iter()
+            .filter_map(|x: &Vec<String>| self.to_casbin_rule(&ptype_c, x))
+            .map(|rule| CasbinRule {
+                valid_from,
+                valid_until,
+                ..rule
+            })
+            .collect::<Vec<CasbinRule>>();
+
+        if let Some(graph) = self.edge_graph(&ptype_c) {
+            return self.add_edges(e, graph, new_rules).await;
+        }
+
+        if self.idempotent_add {
+            return e
+                .upsert_policies(
+                    &self.collection,
+                    &self.layout,
+                    new_rules,
+                    self.deterministic_keys,
+                )
+                .await;
+        }
+
+        e.add_policies(&self.collection, &self.layout, new_rules.clone())
+            .await?;
+        Ok(new_rules)
+    }
+
+    async fn delete_policy(
+        &self,
+        e: &Executor<'_, C>,
+        ptype: &str,
+        rule: Vec<String>,
+    ) -> casbin::Result<bool> {
+        let ptype_c = ptype.to_string();
+
+        let removed = if self.deterministic_keys {
+            let removed = match self.to_casbin_rule(&ptype_c, &rule) {
+                Some(r) => {
+                    e.remove_policies_by_key(
+                        self.rules_collection(&ptype_c),
+                        &self.layout,
+                        vec![r.content_key()],
+                    )
+                    .await?
+                }
+                None => None,
+            };
+            removed.is_some()
+        } else {
+            e.remove_policy(
+                self.rules_collection(&ptype_c),
+                &self.layout,
+                &ptype_c,
+                rule.clone(),
+            )
+            .await?
+        };
+
+        if removed {
+            self.remove_orphans(e, &ptype_c, &[rule]).await?;
+        }
+
+        Ok(removed)
+    }
+
+    /// Removes the vertices of the role graph left without edges by removing
+    /// grouping rules of the ptype.
+    async fn remove_orphans(
+        &self,
+        e: &Executor<'_, C>,
+        ptype: &str,
+        removed: &[Vec<String>],
+    ) -> casbin::Result<()> {
+        match self.edge_graph(ptype) {
+            Some(graph) => graph::remove_orphans(e, graph, &self.layout, removed).await,
+            None => Ok(()),
+        }
+    }
+
+    fn executor(&self) -> Executor<'_, C> {
+        match &*self
+            .transaction
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            Some(tx) => Executor::Transaction(tx.clone()),
+            None => Executor::Database(&self.database),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
+    async fn load_policy(&self, m: &mut dyn Model) -> casbin::Result<()> {
+        let mut batches = pin!(self.policy_batches());
+
+        while let Some(rules) = batches.try_next().await? {
+            load_rules(m, &rules);
+        }
+        self.set_loaded(LoadedPolicy::Whole);
+
+        Ok(())
+    }
+
+    async fn load_filtered_policy<'a>(
+        &mut self,
+        m: &mut dyn Model,
+        f: Filter<'a>,
+    ) -> casbin::Result<()> {
+        let filters = [("p", &f.p), ("g", &f.g)]
+            .iter()
+            .flat_map(|(sec, values)| {
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                section_ptypes(m, sec)
+                    .into_iter()
+                    .map(move |ptype| (ptype, values.clone()))
+            })
+            .collect::<Vec<(String, Vec<String>)>>();
+
+        let (edge_filters, filters): (Vec<_>, Vec<_>) = filters
+            .into_iter()
+            .partition(|(ptype, _)| self.edge_graph(ptype).is_some());
+
+        let mut rules = self
+            .executor()
+            .load_filtered_policy(&self.collection, &self.layout, &filters)
+            .await?;
+
+        if let Some(graph) = &self.role_graph {
+            rules.extend(
+                self.executor()
+                    .load_filtered_policy(&graph.edges, &self.layout, &edge_filters)
+                    .await?,
+            );
+        }
+
+        load_rules(m, &rules);
+
+        let filter = filters
+            .iter()
+            .chain(&edge_filters)
+            .fold(ArangoFilter::new(), |filter, (ptype, values)| {
+                filter.with_group(FilterGroup::exact(ptype, values))
+            });
+        self.set_loaded(LoadedPolicy::Filtered(vec![filter]));
 
         Ok(())
     }
@@ -86,7 +1157,7 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
                 let new_rules = ast
                     .get_policy()
                     .into_iter()
-                    .filter_map(|x: &Vec<String>| map_to_casbin_rule(ptype, x));
+                    .filter_map(|x: &Vec<String>| self.to_casbin_rule(ptype, x));
 
                 rules.extend(new_rules);
             }
@@ -97,21 +1168,35 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
                 let new_rules = ast
                     .get_policy()
                     .into_iter()
-                    .filter_map(|x: &Vec<String>| map_to_casbin_rule(ptype, x));
+                    .filter_map(|x: &Vec<String>| self.to_casbin_rule(ptype, x));
 
                 rules.extend(new_rules);
             }
         }
 
-        self.database.save_policy(rules).await
+        let saved = rule_pairs(&rules);
+        let adapter = &*self;
+        adapter
+            .audited(AuditOperation::SavePolicy, |e| async move {
+                let rules = adapter.keep_windows(&e, rules).await?;
+                adapter.replace_policy(&e, rules).await?;
+                Ok(((), Some(saved)))
+            })
+            .await
     }
 
     async fn clear_policy(&mut self) -> casbin::Result<()> {
-        self.database.clear_policy().await
+        let adapter = &*self;
+        adapter
+            .audited(AuditOperation::ClearPolicy, |e| async move {
+                adapter.clear_rules(&e).await?;
+                Ok(((), Some(vec![])))
+            })
+            .await
     }
 
     fn is_filtered(&self) -> bool {
-        self.is_filtered
+        matches!(self.loaded(), LoadedPolicy::Filtered(_))
     }
 
     async fn add_policy(
@@ -120,12 +1205,8 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
         ptype: &str,
         rule: Vec<String>,
     ) -> casbin::Result<bool> {
-        let ptype_c = ptype.to_string();
-
-        if let Some(new_rule) = map_to_casbin_rule(&ptype_c, &rule) {
-            return self.database.add_policy(new_rule).await;
-        }
-        Ok(false)
+        self.add_policy_with_validity(ptype, rule, Validity::default())
+            .await
     }
 
     async fn add_policies(
@@ -134,14 +1215,8 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
         ptype: &str,
         rules: Vec<Vec<String>>,
     ) -> casbin::Result<bool> {
-        let ptype_c = ptype.to_string();
-
-        let new_rules = rules
-            .iter()
-            .filter_map(|x: &Vec<String>| map_to_casbin_rule(&ptype_c, x))
-            .collect::<Vec<CasbinRule>>();
-
-        return self.database.add_policies(new_rules).await;
+        self.add_policies_with_validity(ptype, rules, Validity::default())
+            .await
     }
 
     async fn remove_policy(
@@ -150,8 +1225,7 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
         ptype: &str,
         rule: Vec<String>,
     ) -> casbin::Result<bool> {
-        let ptype_c = ptype.to_string();
-        self.database.remove_policy(&ptype_c, rule).await
+        self.revoke_policy(ptype, rule).await
     }
 
     async fn remove_policies(
@@ -160,8 +1234,8 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
         ptype: &str,
         rules: Vec<Vec<String>>,
     ) -> casbin::Result<bool> {
-        let ptype_c = ptype.to_string();
-        self.database.remove_policies(&ptype_c, rules).await
+        let removed = self.remove_policies_returning(ptype, rules).await?;
+        Ok(removed.is_some())
     }
 
     async fn remove_filtered_policy(
@@ -171,51 +1245,44 @@ impl<C: ClientExt + Send> Adapter for ArangorsAdapter<C> {
         field_index: usize,
         field_values: Vec<String>,
     ) -> casbin::Result<bool> {
-        if field_index <= 5 && !field_values.is_empty() {
+        let removed = self
+            .remove_filtered_policy_returning(pt, field_index, field_values)
+            .await?;
+        Ok(!removed.is_empty())
+    }
+}
 
-            let ptype_c = pt.to_string();
+/// The rules as `(ptype, values)` pairs.
+fn rule_pairs(rules: &[CasbinRule]) -> Vec<(String, Vec<String>)> {
+    rules
+        .iter()
+        .filter_map(|rule| Some((rule.ptype.clone(), load_policy_line(rule)?)))
+        .collect()
+}
 
-            let t = self.database.remove_filtered_policy(&ptype_c, field_index, field_values).await
-                .map_err(|e| casbin::error::AdapterError(Box::new(e)).into());
-            t
-        } else {
-            Ok(false)
-        }
-    }
+/// Whether any rule was inserted, and the rules to audit as added: only
+/// those inserted, not those already stored.
+fn added(inserted: &[CasbinRule]) -> Audited<bool> {
+    let rules = rule_pairs(inserted);
+    (
+        !rules.is_empty(),
+        Some(rules).filter(|rules| !rules.is_empty()),
+    )
 }
 
-fn map_to_casbin_rule(ptype: &str, rule: &[String]) -> Option<CasbinRule> {
-    if ptype.trim().is_empty() || rule.is_empty() {
-        return None;
-    }
-
-    let new_rule = CasbinRule {
-        _key: None,
-        ptype: ptype.to_owned(),
-        v0: rule[0].to_owned(),
-        v1: rule
-            .get(1)
-            .map(String::to_owned)
-            .unwrap_or(String::from("")),
-        v2: rule
-            .get(2)
-            .map(String::to_owned)
-            .unwrap_or(String::from("")),
-        v3: rule
-            .get(3)
-            .map(String::to_owned)
-            .unwrap_or(String::from("")),
-        v4: rule
-            .get(4)
-            .map(String::to_owned)
-            .unwrap_or(String::from("")),
-        v5: rule
-            .get(5)
-            .map(String::to_owned)
-            .unwrap_or(String::from("")),
-    };
-
-    Some(new_rule)
+/// Adds the rules to the policy of the model.
+fn load_rules(m: &mut dyn Model, rules: &[CasbinRule]) {
+    for casbin_rule in rules {
+        if let Some(rule) = load_policy_line(casbin_rule) {
+            if let Some(ref sec) = casbin_rule.ptype.chars().next().map(|x| x.to_string()) {
+                if let Some(t1) = m.get_mut_model().get_mut(sec) {
+                    if let Some(t2) = t1.get_mut(&casbin_rule.ptype) {
+                        t2.get_mut_policy().insert(rule);
+                    }
+                }
+            }
+        }
+    }
 }
 
 fn load_policy_line(casbin_rule: &CasbinRule) -> Option<Vec<String>> {
@@ -227,52 +1294,16 @@ fn load_policy_line(casbin_rule: &CasbinRule) -> Option<Vec<String>> {
 }
 
 fn normalize_policy(casbin_rule: &CasbinRule) -> Option<Vec<String>> {
-    let mut result = vec![
-        &casbin_rule.v0,
-        &casbin_rule.v1,
-        &casbin_rule.v2,
-        &casbin_rule.v3,
-        &casbin_rule.v4,
-        &casbin_rule.v5,
-    ];
-
-    while let Some(last) = result.last() {
-        if last.is_empty() {
-            result.pop();
-        } else {
-            break;
-        }
-    }
-
-    if !result.is_empty() {
-        return Some(result.iter().map(|&x| x.to_owned()).collect());
+    if !casbin_rule.values.is_empty() {
+        return Some(casbin_rule.values.clone());
     }
 
     None
 }
 
-fn load_filtered_policy_line(casbin_rule: &CasbinRule, f: &Filter) -> Option<(bool, Vec<String>)> {
-    if let Some(sec) = casbin_rule.ptype.chars().next() {
-        if let Some(policy) = normalize_policy(casbin_rule) {
-            let mut is_filtered = true;
-            if sec == 'p' {
-                for (i, rule) in f.p.iter().enumerate() {
-                    if !rule.is_empty() && rule != &policy[i] {
-                        is_filtered = false
-                    }
-                }
-            } else if sec == 'g' {
-                for (i, rule) in f.g.iter().enumerate() {
-                    if !rule.is_empty() && rule != &policy[i] {
-                        is_filtered = false
-                    }
-                }
-            } else {
-                return None;
-            }
-            return Some((is_filtered, policy));
-        }
-    }
-
-    None
+fn section_ptypes(m: &dyn Model, sec: &str) -> Vec<String> {
+    m.get_model()
+        .get(sec)
+        .map(|ast_map| ast_map.keys().cloned().collect())
+        .unwrap_or_default()
 }
@@ -194,3 +194,1979 @@ async fn test_adapter() {
     assert!(!e.enforce(("bob", "domain2", "data2", "read")).unwrap());
     assert!(!e.enforce(("bob", "domain2", "data2", "write")).unwrap());
 }
+
+#[tokio::test]
+async fn test_adapters_on_different_collections_are_isolated() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.create_collection("casbin_app_a").await;
+    let _ = db.create_collection("casbin_app_b").await;
+
+    let mut adapter_a = ArangorsAdapter::new_with_collection(db.clone(), "casbin_app_a");
+    let mut adapter_b = ArangorsAdapter::new_with_collection(db, "casbin_app_b");
+
+    assert!(adapter_a.clear_policy().await.is_ok());
+    assert!(adapter_b.clear_policy().await.is_ok());
+
+    assert!(adapter_a
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .is_ok());
+    assert!(adapter_b
+        .add_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .is_ok());
+
+    // removing from one collection must leave the other untouched
+    assert!(!adapter_a
+        .remove_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap());
+    assert!(!adapter_a
+        .remove_filtered_policy("", "p", 0, to_owned(vec!["bob"]))
+        .await
+        .unwrap());
+
+    let e_a = Enforcer::new("examples/rbac_model.conf", adapter_a)
+        .await
+        .unwrap();
+    let e_b = Enforcer::new("examples/rbac_model.conf", adapter_b)
+        .await
+        .unwrap();
+
+    assert!(e_a.enforce(("alice", "data1", "read")).unwrap());
+    assert!(!e_a.enforce(("bob", "data2", "write")).unwrap());
+    assert!(e_b.enforce(("bob", "data2", "write")).unwrap());
+    assert!(!e_b.enforce(("alice", "data1", "read")).unwrap());
+}
+
+#[tokio::test]
+async fn test_new_with_setup_bootstraps_schema() {
+    use arangors::index::{Index, IndexSettings};
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_setup").await;
+
+    let mut adapter = ArangorsAdapter::new_with_setup(db.clone(), "casbin_setup")
+        .await
+        .unwrap();
+
+    assert!(adapter
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .is_ok());
+    // the unique index rejects duplicates
+    let err = adapter
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&err),
+        Some(ArangorsAdapterError::DuplicateRule { error_num: 1210, .. })
+    ));
+
+    // running the setup again is a no-op
+    assert!(adapter.ensure_schema().await.is_ok());
+
+    let _ = db.drop_collection("casbin_setup_bad").await;
+    db.create_collection("casbin_setup_bad").await.unwrap();
+    let index = Index::builder()
+        .name("casbin_rule_unique")
+        .fields(vec!["ptype".to_string(), "v0".to_string()])
+        .settings(IndexSettings::Persistent {
+            unique: true,
+            sparse: false,
+            deduplicate: false,
+        })
+        .build();
+    db.create_index("casbin_setup_bad", &index).await.unwrap();
+
+    let err = ArangorsAdapter::new_with_setup(db.clone(), "casbin_setup_bad")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&err),
+        Some(ArangorsAdapterError::IndexMismatch { index, .. }) if index == "casbin_rule_unique"
+    ));
+
+    // a unique index over fewer rule attributes, under another name
+    let _ = db.drop_collection("casbin_setup_partial").await;
+    db.create_collection("casbin_setup_partial").await.unwrap();
+    let index = Index::builder()
+        .name("by_hand")
+        .fields(to_owned(vec!["ptype", "v0", "v1"]))
+        .settings(IndexSettings::Persistent {
+            unique: true,
+            sparse: false,
+            deduplicate: false,
+        })
+        .build();
+    db.create_index("casbin_setup_partial", &index)
+        .await
+        .unwrap();
+
+    let err = ArangorsAdapter::new_with_setup(db, "casbin_setup_partial")
+        .await
+        .err()
+        .unwrap();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&err),
+        Some(ArangorsAdapterError::IndexMismatch { index, fields, .. })
+            if index == "by_hand" && fields == &to_owned(vec!["ptype", "v0", "v1"])
+    ));
+}
+
+#[test]
+fn test_filtered_policy_query() {
+    use crate::casbin_dao::filtered_policy_query;
+    use serde_json::Value;
+
+    let (query, vars) = filtered_policy_query(&Layout::default(), &[
+        ("p".to_owned(), to_owned(vec!["", "domain1"])),
+        ("g".to_owned(), to_owned(vec!["", "", "domain1"])),
+    ]);
+
+    assert_eq!(
+        query,
+        format!(
+            "FOR r IN @@collection\n    FILTER (r.ptype == @t0 AND r.v1 == @f0_1) OR (r.ptype == @t1 AND r.v2 == @f1_2)\n    FILTER {}\n    RETURN r",
+            crate::validity::validity_filter("r")
+        )
+    );
+    assert_eq!(
+        vars,
+        vec![
+            ("t0".to_owned(), Value::from("p")),
+            ("f0_1".to_owned(), Value::from("domain1")),
+            ("t1".to_owned(), Value::from("g")),
+            ("f1_2".to_owned(), Value::from("domain1")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_save_policy_replaces_stored_policy() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_save")
+        .await
+        .unwrap();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+
+    // saving the same model twice must not trip the unique index
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let mut e_domains = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e_domains.get_mut_model()).await.is_ok());
+
+    let e = Enforcer::new("examples/rbac_with_domains_model.conf", adapter)
+        .await
+        .unwrap();
+
+    assert!(e.enforce(("alice", "domain1", "data1", "read")).unwrap());
+    assert!(e.enforce(("bob", "domain2", "data2", "write")).unwrap());
+    // rules of the previously saved model are gone
+    assert!(!e.has_policy(to_owned(vec!["alice", "data1", "read"])));
+    assert!(!e.has_grouping_policy(to_owned(vec!["alice", "data2_admin"])));
+}
+
+#[tokio::test]
+async fn test_batches_and_transactions_are_atomic() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_tx")
+        .await
+        .unwrap();
+    assert!(adapter.clear_policy().await.is_ok());
+
+    assert!(adapter
+        .add_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .is_ok());
+
+    // the duplicate makes the whole batch fail, alice must not be stored
+    assert!(adapter
+        .add_policies(
+            "",
+            "p",
+            vec![
+                to_owned(vec!["alice", "data1", "read"]),
+                to_owned(vec!["bob", "data2", "write"]),
+            ]
+        )
+        .await
+        .is_err());
+    assert!(!adapter
+        .remove_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+
+    let handle = adapter.transaction_handle();
+
+    assert!(handle.begin().await.is_ok());
+    assert!(handle.begin().await.is_err());
+    assert!(adapter
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .is_ok());
+    assert!(adapter
+        .remove_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap());
+    assert!(handle.abort().await.is_ok());
+    assert!(handle.commit().await.is_err());
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(!e.enforce(("alice", "data1", "read")).unwrap());
+    assert!(e.enforce(("bob", "data2", "write")).unwrap());
+
+    // the handle keeps working once the adapter belongs to the enforcer
+    assert!(handle.begin().await.is_ok());
+    assert!(e
+        .add_policy(to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+    assert!(e
+        .remove_policy(to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap());
+    assert!(handle.commit().await.is_ok());
+
+    e.load_policy().await.unwrap();
+    assert!(e.enforce(("alice", "data1", "read")).unwrap());
+    assert!(!e.enforce(("bob", "data2", "write")).unwrap());
+}
+
+#[test]
+fn test_client_errors_are_classified() {
+    use arangors::ClientError;
+
+    fn arango_error(code: u16, error_num: u16) -> ClientError {
+        ClientError::Arango(
+            serde_json::from_value(serde_json::json!({
+                "code": code,
+                "errorNum": error_num,
+                "errorMessage": "message",
+            }))
+            .unwrap(),
+        )
+    }
+
+    assert!(matches!(
+        ArangorsAdapterError::from(arango_error(409, 1210)),
+        ArangorsAdapterError::DuplicateRule { error_num: 1210, .. }
+    ));
+    assert!(matches!(
+        ArangorsAdapterError::from(arango_error(404, 1203)),
+        ArangorsAdapterError::CollectionNotFound { error_num: 1203, .. }
+    ));
+    assert!(matches!(
+        ArangorsAdapterError::from(arango_error(401, 11)),
+        ArangorsAdapterError::Unauthorized { error_num: 11, .. }
+    ));
+    assert!(matches!(
+        ArangorsAdapterError::from(arango_error(408, 18)),
+        ArangorsAdapterError::Timeout {
+            error_num: Some(18),
+            ..
+        }
+    ));
+    assert!(matches!(
+        ArangorsAdapterError::from(arango_error(400, 1501)),
+        ArangorsAdapterError::Arango {
+            code: 400,
+            error_num: 1501,
+            ..
+        }
+    ));
+
+    let err: casbin::Error = ArangorsAdapterError::from(arango_error(409, 1210)).into();
+    assert_eq!(
+        ArangorsAdapterError::from_casbin(&err).and_then(|e| e.error_num()),
+        Some(1210)
+    );
+}
+
+#[tokio::test]
+async fn test_idempotent_add() {
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_idempotent")
+        .await
+        .unwrap()
+        .with_idempotent_add(true);
+    assert!(adapter.clear_policy().await.is_ok());
+
+    assert!(adapter
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+    assert!(!adapter
+        .add_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+
+    assert!(adapter
+        .add_policies(
+            "",
+            "p",
+            vec![
+                to_owned(vec!["alice", "data1", "read"]),
+                to_owned(vec!["bob", "data2", "write"]),
+            ]
+        )
+        .await
+        .unwrap());
+    assert!(!adapter
+        .add_policies(
+            "",
+            "p",
+            vec![
+                to_owned(vec!["alice", "data1", "read"]),
+                to_owned(vec!["bob", "data2", "write"]),
+            ]
+        )
+        .await
+        .unwrap());
+
+    assert!(adapter
+        .remove_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap());
+}
+
+#[test]
+fn test_content_key() {
+    let key = |ptype: &str, rule: Vec<&str>| {
+        Layout::default()
+            .rule(ptype, &to_owned(rule))
+            .unwrap()
+            .content_key()
+    };
+
+    let k = key("p", vec!["alice", "data1", "read"]);
+    assert_eq!(k.len(), 64);
+    assert!(k.chars().all(|c| c.is_ascii_hexdigit()));
+
+    assert_eq!(k, key("p", vec!["alice", "data1", "read"]));
+    assert_eq!(k, key("p", vec!["alice", "data1", "read", ""]));
+    assert_ne!(k, key("p2", vec!["alice", "data1", "read"]));
+    assert_ne!(key("p", vec!["ab", "c"]), key("p", vec!["a", "bc"]));
+}
+
+#[test]
+fn test_long_rules_round_trip() {
+    let layout = Layout::default();
+    let rule = to_owned(vec!["alice", "data1", "read", "", "", "", "ip", "", "eu"]);
+    let casbin_rule = layout.rule("p", &rule).unwrap();
+    assert_eq!(normalize_policy(&casbin_rule).unwrap(), rule);
+
+    let doc = layout.encode(&casbin_rule).unwrap();
+    assert_eq!(doc["v5"], "");
+    assert_eq!(doc["extra"], serde_json::json!(["ip", "", "eu"]));
+
+    // documents written before rules could have more than six values
+    let short = layout
+        .rule("p", &to_owned(vec!["alice", "data1", "read"]))
+        .unwrap();
+    assert!(layout.encode(&short).unwrap().get("extra").is_none());
+    let legacy = layout
+        .decode(serde_json::json!({
+            "_key": "1", "_id": "casbin/1", "_rev": "_a",
+            "ptype": "p", "v0": "alice", "v1": "data1", "v2": "read", "v3": "", "v4": "", "v5": ""
+        }))
+        .unwrap();
+    assert_eq!(
+        normalize_policy(&legacy).unwrap(),
+        to_owned(vec!["alice", "data1", "read"])
+    );
+
+    // trailing empty values don't change the rule
+    let padded = to_owned(vec!["alice", "data1", "read", "", "", "", "", ""]);
+    assert_eq!(
+        layout.rule("p", &padded).unwrap().content_key(),
+        short.content_key()
+    );
+    assert_ne!(casbin_rule.content_key(), short.content_key());
+
+    assert_eq!(layout.field("p", 5), "v5");
+    assert_eq!(layout.field("p", 8), "extra[2]");
+}
+
+#[tokio::test]
+async fn test_rules_with_more_than_six_values() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_long")
+        .await
+        .unwrap();
+    assert!(adapter.clear_policy().await.is_ok());
+
+    let eu = to_owned(vec!["alice", "data1", "read", "a", "b", "c", "d", "eu"]);
+    let us = to_owned(vec!["alice", "data1", "read", "a", "b", "c", "d", "us"]);
+    assert!(adapter.add_policy("", "p", eu.clone()).await.unwrap());
+    // rules differing past the sixth value are distinct for the unique index
+    assert!(adapter.add_policy("", "p", us.clone()).await.unwrap());
+
+    let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+        .await
+        .unwrap();
+    adapter.load_policy(&mut m).await.unwrap();
+    let mut policy = m.get_policy("p", "p");
+    policy.sort();
+    assert_eq!(policy, vec![eu.clone(), us]);
+
+    // the shorter prefix is a different rule
+    assert!(!adapter
+        .remove_policy("", "p", eu[..6].to_vec())
+        .await
+        .unwrap());
+    assert!(adapter.remove_policy("", "p", eu).await.unwrap());
+}
+
+#[test]
+fn test_array_layout() {
+    use crate::casbin_dao::filtered_removal_query;
+    use serde_json::{json, Value};
+
+    let array = Layout::from(DocumentLayout::Array);
+    let mut rule = array
+        .rule("g", &to_owned(vec!["alice", "admin", ""]))
+        .unwrap();
+    rule._key = Some("k".to_owned());
+
+    let doc = array.encode(&rule).unwrap();
+    assert_eq!(doc, json!({"_key": "k", "ptype": "g", "rule": ["alice", "admin"]}));
+
+    let back = array.decode(doc).unwrap();
+    assert_eq!(back._key.as_deref(), Some("k"));
+    assert_eq!(
+        normalize_policy(&back).unwrap(),
+        to_owned(vec!["alice", "admin"])
+    );
+    assert!(array
+        .decode(json!({"ptype": "p", "v0": "alice"}))
+        .is_err());
+
+    let (query, vars) = filtered_removal_query(
+        &array,
+        "p",
+        1,
+        &to_owned(vec!["data1", "", "eu"]),
+    );
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND r.rule[1] == @f0 AND r.rule[3] == @f2\n    REMOVE r IN @@collection\n    RETURN OLD"
+    );
+    assert_eq!(
+        vars,
+        vec![
+            ("ptype".to_owned(), Value::from("p")),
+            ("f0".to_owned(), Value::from("data1")),
+            ("f2".to_owned(), Value::from("eu")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_array_layout_and_migration() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_layout").await;
+
+    let mut columns = ArangorsAdapter::new_with_setup(db.clone(), "casbin_layout")
+        .await
+        .unwrap();
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(columns.save_policy(e.get_mut_model()).await.is_ok());
+
+    let mut array = ArangorsAdapter::new_with_collection(db.clone(), "casbin_layout")
+        .with_layout(DocumentLayout::Array);
+    array.migrate_layout(DocumentLayout::Columns).await.unwrap();
+
+    let collection = db.collection("casbin_layout").await.unwrap();
+    let aql = arangors::AqlQuery::builder()
+        .query("FOR r IN @@collection FILTER r.ptype == 'g' RETURN r.rule")
+        .bind_var("@collection", collection.name())
+        .build();
+    let stored: Vec<Vec<String>> = db.aql_query(aql).await.unwrap();
+    assert_eq!(stored, vec![to_owned(vec!["alice", "data2_admin"])]);
+
+    assert!(array
+        .remove_filtered_policy("", "p", 1, to_owned(vec!["data2"]))
+        .await
+        .unwrap());
+    assert!(array
+        .remove_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+    assert!(array
+        .add_policy("", "p", to_owned(vec!["carol", "data3", "read"]))
+        .await
+        .unwrap());
+
+    let e = Enforcer::new("examples/rbac_model.conf", array).await.unwrap();
+    assert_eq!(
+        e.get_policy(),
+        vec![to_owned(vec!["carol", "data3", "read"])]
+    );
+    assert!(e.has_grouping_policy(to_owned(vec!["alice", "data2_admin"])));
+
+    // and back
+    let columns = columns.with_layout(DocumentLayout::Columns);
+    columns.migrate_layout(DocumentLayout::Array).await.unwrap();
+    let e = Enforcer::new("examples/rbac_model.conf", columns).await.unwrap();
+    assert_eq!(
+        e.get_policy(),
+        vec![to_owned(vec!["carol", "data3", "read"])]
+    );
+}
+
+#[tokio::test]
+async fn test_migration_from_hand_made_index() {
+    use arangors::index::{Index, IndexSettings};
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_hand_made").await;
+    db.create_collection("casbin_hand_made").await.unwrap();
+
+    // as the README used to suggest
+    let fields = ["ptype", "v0", "v1", "v2", "v3", "v4", "v5"];
+    let index = Index::builder()
+        .name("by_hand")
+        .fields(fields.iter().map(|f| f.to_string()).collect())
+        .settings(IndexSettings::Persistent {
+            unique: true,
+            sparse: false,
+            deduplicate: false,
+        })
+        .build();
+    db.create_index("casbin_hand_made", &index).await.unwrap();
+
+    let mut columns = ArangorsAdapter::new_with_collection(db.clone(), "casbin_hand_made");
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(columns.save_policy(e.get_mut_model()).await.is_ok());
+
+    let array = ArangorsAdapter::new_with_collection(db.clone(), "casbin_hand_made")
+        .with_layout(DocumentLayout::Array);
+    array.migrate_layout(DocumentLayout::Columns).await.unwrap();
+
+    let indexes = db.indexes("casbin_hand_made").await.unwrap().indexes;
+    assert!(indexes.iter().all(|i| i.name != "by_hand"));
+    let e = Enforcer::new("examples/rbac_model.conf", array)
+        .await
+        .unwrap();
+    assert_eq!(e.get_policy().len(), 4);
+}
+
+#[tokio::test]
+async fn test_named_layout() {
+    use casbin::prelude::*;
+    use serde_json::json;
+
+    let m = DefaultModel::from_file("examples/rbac_with_domains_model.conf")
+        .await
+        .unwrap();
+    let layout = Layout::named(&m);
+
+    let rule = layout
+        .rule("p", &to_owned(vec!["admin", "domain1", "data1"]))
+        .unwrap();
+    let doc = layout.encode(&rule).unwrap();
+    assert_eq!(
+        doc,
+        json!({"ptype": "p", "sub": "admin", "dom": "domain1", "obj": "data1", "act": ""})
+    );
+    assert_eq!(
+        normalize_policy(&layout.decode(doc).unwrap()).unwrap(),
+        to_owned(vec!["admin", "domain1", "data1"])
+    );
+
+    let rule = layout
+        .rule("g", &to_owned(vec!["alice", "admin", "domain1"]))
+        .unwrap();
+    assert_eq!(
+        layout.encode(&rule).unwrap(),
+        json!({"ptype": "g", "user": "alice", "role": "admin", "domain": "domain1"})
+    );
+
+    assert_eq!(layout.field("p", 1), "`dom`");
+    assert_eq!(layout.field("p", 4), "`v4`");
+    assert_eq!(layout.domain_fields(), to_owned(vec!["`v2`", "`domain`"]));
+    assert_eq!(
+        layout.index_fields(),
+        to_owned(vec!["ptype", "user", "role", "domain", "sub", "dom", "obj", "act"])
+    );
+    assert_eq!(
+        layout.upsert_search("g"),
+        "{ ptype: r.ptype, `user`: r.`user`, `role`: r.`role`, `domain`: r.`domain` }"
+    );
+}
+
+#[tokio::test]
+async fn test_named_fields_adapter() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_named").await;
+
+    let m = DefaultModel::from_file("examples/rbac_with_domains_model.conf")
+        .await
+        .unwrap();
+    let mut adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_named")
+        .with_named_fields(&m);
+    adapter.ensure_schema().await.unwrap();
+
+    let mut e = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let aql = arangors::AqlQuery::builder()
+        .query("FOR r IN casbin_named FILTER r.ptype == 'g' SORT r.user RETURN [r.user, r.role, r.domain]")
+        .build();
+    let stored: Vec<Vec<String>> = db.aql_query(aql).await.unwrap();
+    assert_eq!(
+        stored,
+        vec![
+            to_owned(vec!["alice", "admin", "domain1"]),
+            to_owned(vec!["bob", "admin", "domain2"]),
+        ]
+    );
+
+    assert!(adapter
+        .remove_filtered_policy("", "p", 1, to_owned(vec!["domain2"]))
+        .await
+        .unwrap());
+    assert!(adapter
+        .remove_policy("", "g", to_owned(vec!["bob", "admin", "domain2"]))
+        .await
+        .unwrap());
+
+    let e = Enforcer::new("examples/rbac_with_domains_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("alice", "domain1", "data1", "read")).unwrap());
+    assert!(!e.enforce(("bob", "domain2", "data2", "read")).unwrap());
+    assert_eq!(e.get_policy().len(), 2);
+}
+
+#[test]
+fn test_null_absent_layout() {
+    use crate::casbin_dao::{exact_removal_query, filtered_removal_query, padded_rule_query};
+    use crate::layout::Shape;
+    use serde_json::{json, Value};
+
+    let nulls = Layout {
+        null_absent: true,
+        ..Layout::default()
+    };
+    let empty = to_owned(vec!["alice", "data1", ""]);
+
+    let rule = nulls.rule("p", &empty).unwrap();
+    let doc = nulls.encode(&rule).unwrap();
+    assert_eq!(
+        doc,
+        json!({"ptype": "p", "v0": "alice", "v1": "data1", "v2": "", "v3": null, "v4": null, "v5": null})
+    );
+    assert_eq!(normalize_policy(&nulls.decode(doc.clone()).unwrap()).unwrap(), empty);
+    // padded with "", the empty value can't be told apart
+    assert_eq!(
+        normalize_policy(&Layout::default().decode(doc).unwrap()).unwrap(),
+        to_owned(vec!["alice", "data1"])
+    );
+
+    let absent = nulls.rule("p", &empty[..2]).unwrap();
+    assert_ne!(rule.content_key(), absent.content_key());
+    assert_eq!(
+        absent.content_key(),
+        Layout::default().rule("p", &empty).unwrap().content_key()
+    );
+
+    let (query, vars) = exact_removal_query(&nulls, "p", &empty);
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND r.v0 == @v0 AND r.v1 == @v1 AND r.v2 == @v2 AND r.v3 == @v3 AND r.v4 == @v4 AND r.v5 == @v5 AND r.extra[0] == null\n    REMOVE r IN @@collection\n    RETURN 1"
+    );
+    assert_eq!(vars[3], ("v2".to_owned(), Value::from("")));
+    assert_eq!(vars[4], ("v3".to_owned(), Value::Null));
+
+    // empty field values match anything, whichever way absent ones are stored
+    let (query, _) = filtered_removal_query(&nulls, "p", 0, &to_owned(vec!["", "data1"]));
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND r.v1 == @f1\n    REMOVE r IN @@collection\n    RETURN OLD"
+    );
+
+    // a rule filling every column with a trailing "" is told apart from padding
+    let full = to_owned(vec!["alice", "data1", "read", "a", "b", ""]);
+    let doc = nulls.encode(&nulls.rule("p", &full).unwrap()).unwrap();
+    assert_eq!(doc["extra"], Value::Null);
+    assert!(doc.as_object().unwrap().contains_key("extra"));
+    assert_eq!(normalize_policy(&nulls.decode(doc).unwrap()).unwrap(), full);
+
+    let (query, vars) = padded_rule_query(&nulls).unwrap();
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER (r.v5 == \"\" AND NOT HAS(r, @n0))\n    LIMIT 1\n    RETURN 1"
+    );
+    assert_eq!(vars, vec![("n0".to_owned(), Value::from("extra"))]);
+    let array = Layout {
+        shape: Shape::Array,
+        null_absent: true,
+    };
+    assert!(padded_rule_query(&array).is_none());
+}
+
+#[tokio::test]
+async fn test_null_absent_adapter() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_nulls").await;
+
+    let mut padded = ArangorsAdapter::new_with_setup(db.clone(), "casbin_nulls")
+        .await
+        .unwrap();
+    assert!(padded
+        .add_policy("", "p", to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap());
+
+    let mut nulls =
+        ArangorsAdapter::new_with_collection(db, "casbin_nulls").with_null_absent(true);
+    // rules padded with "" have to be converted first
+    let err = nulls.ensure_schema().await.unwrap_err();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&err),
+        Some(ArangorsAdapterError::PaddedRules { .. })
+    ));
+    nulls.migrate_layout(DocumentLayout::Columns).await.unwrap();
+
+    let empty = to_owned(vec!["alice", "data1", ""]);
+    assert!(nulls.add_policy("", "p", empty.clone()).await.unwrap());
+    // a different rule for the unique index
+    assert!(nulls
+        .add_policy("", "p", empty[..2].to_vec())
+        .await
+        .unwrap());
+
+    assert!(nulls
+        .remove_filtered_policy("", "p", 0, to_owned(vec!["bob"]))
+        .await
+        .unwrap());
+    assert!(nulls
+        .remove_policy("", "p", empty[..2].to_vec())
+        .await
+        .unwrap());
+
+    let mut m = DefaultModel::from_file("examples/rbac_model.conf")
+        .await
+        .unwrap();
+    nulls.load_policy(&mut m).await.unwrap();
+    assert_eq!(m.get_policy("p", "p"), vec![empty]);
+}
+
+#[tokio::test]
+async fn test_stream_policies() {
+    use casbin::prelude::*;
+    use futures_util::TryStreamExt;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_stream")
+        .await
+        .unwrap()
+        .with_batch_size(2);
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let mut streamed: Vec<(String, Vec<String>)> =
+        adapter.stream_policies().try_collect().await.unwrap();
+    streamed.sort();
+    assert_eq!(streamed.len(), 5);
+    assert_eq!(
+        streamed[0],
+        ("g".to_owned(), to_owned(vec!["alice", "data2_admin"]))
+    );
+
+    // loaded a batch at a time
+    let e = Enforcer::new("examples/rbac_model.conf", adapter.with_batch_size(1))
+        .await
+        .unwrap();
+    assert_eq!(e.get_policy().len(), 4);
+    assert!(e.enforce(("alice", "data2", "read")).unwrap());
+}
+
+#[test]
+fn test_batch_removal_query() {
+    use crate::casbin_dao::batch_removal_query;
+    use serde_json::json;
+
+    let rules = vec![
+        to_owned(vec!["alice", "data1", "read", ""]),
+        to_owned(vec!["alice", "data1", "read", "a", "b", "c", "eu"]),
+    ];
+
+    let (query, vars) = batch_removal_query(&Layout::default(), "p", rules.clone());
+    assert!(query.contains(
+        "FILTER r.ptype == @ptype AND r.v0 == rule[0] AND r.v1 == rule[1] AND r.v2 == rule[2] AND r.v3 == rule[3] AND r.v4 == rule[4] AND r.v5 == rule[5] AND r.extra[0] == rule[6] AND r.extra[1] == null"
+    ));
+    assert_eq!(
+        vars[1].1,
+        json!([
+            ["alice", "data1", "read", "", "", "", null],
+            ["alice", "data1", "read", "a", "b", "c", "eu"]
+        ])
+    );
+
+    let array = Layout::from(DocumentLayout::Array);
+    let (query, vars) = batch_removal_query(&array, "p", rules);
+    assert!(query.contains("FILTER r.ptype == @ptype AND r.rule == rule\n"));
+    assert_eq!(vars[1].1[0], json!(["alice", "data1", "read"]));
+}
+
+#[tokio::test]
+async fn test_remove_policies_is_all_or_nothing() {
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_remove")
+        .await
+        .unwrap();
+    assert!(adapter.clear_policy().await.is_ok());
+
+    let alice = to_owned(vec!["alice", "data1", "read"]);
+    let bob = to_owned(vec!["bob", "data2", "write"]);
+    let carol = to_owned(vec!["carol", "data3", "read"]);
+    assert!(adapter
+        .add_policies("", "p", vec![alice.clone(), bob.clone()])
+        .await
+        .unwrap());
+
+    // carol is missing, so alice stays
+    assert!(!adapter
+        .remove_policies("", "p", vec![alice.clone(), carol])
+        .await
+        .unwrap());
+
+    let removed = adapter
+        .remove_policies_returning("p", vec![bob.clone(), alice.clone(), alice.clone()])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(removed.len(), 2);
+    assert!(removed.contains(&alice) && removed.contains(&bob));
+
+    assert!(!adapter
+        .remove_policies("", "p", vec![alice])
+        .await
+        .unwrap());
+}
+
+#[test]
+fn test_filtered_removal_query_past_the_sixth_value() {
+    use crate::casbin_dao::filtered_removal_query;
+    use serde_json::Value;
+
+    let (query, vars) = filtered_removal_query(
+        &Layout::default(),
+        "p",
+        5,
+        &to_owned(vec!["c", "", "eu"]),
+    );
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND r.v5 == @f0 AND r.extra[1] == @f2\n    REMOVE r IN @@collection\n    RETURN OLD"
+    );
+    assert_eq!(vars[2], ("f2".to_owned(), Value::from("eu")));
+}
+
+#[tokio::test]
+async fn test_remove_filtered_policy_returns_removed_rules() {
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_filtered")
+        .await
+        .unwrap();
+    assert!(adapter.clear_policy().await.is_ok());
+
+    let eu = to_owned(vec!["alice", "data1", "read", "a", "b", "c", "d", "eu"]);
+    let us = to_owned(vec!["alice", "data1", "read", "a", "b", "c", "d", "us"]);
+    let short = to_owned(vec!["alice", "data1", "read"]);
+    assert!(adapter
+        .add_policies("", "p", vec![eu.clone(), us.clone(), short.clone()])
+        .await
+        .unwrap());
+
+    // past v5, and with more values than the sixth position leaves room for
+    let removed = adapter
+        .remove_filtered_policy_returning("p", 5, to_owned(vec!["c", "", "eu"]))
+        .await
+        .unwrap();
+    assert_eq!(removed, vec![eu]);
+
+    assert!(!adapter
+        .remove_filtered_policy("", "p", 7, to_owned(vec!["eu"]))
+        .await
+        .unwrap());
+    assert!(!adapter
+        .remove_filtered_policy("", "p", 0, vec![])
+        .await
+        .unwrap());
+
+    let mut removed = adapter
+        .remove_filtered_policy_returning("p", 0, to_owned(vec!["alice"]))
+        .await
+        .unwrap();
+    removed.sort();
+    assert_eq!(removed, vec![short, us]);
+}
+
+#[test]
+fn test_matching_policy_query() {
+    use crate::casbin_dao::matching_policy_query;
+    use crate::filter::glob_to_like;
+    use serde_json::Value;
+
+    assert_eq!(glob_to_like("/projects/42/*"), "/projects/42/%");
+    assert_eq!(glob_to_like("a?_100%\\"), "a_\\_100\\%\\\\");
+
+    let matchers = vec![
+        FieldMatcher::Glob("/projects/42/*".to_owned()),
+        FieldMatcher::Any,
+        FieldMatcher::Regex("^eu-".to_owned()),
+    ];
+    let (query, vars) = matching_policy_query(&Layout::default(), "p", 1, &matchers, true);
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND LIKE(r.v1, @m0) AND REGEX_TEST(r.v3, @m2)\n    RETURN r"
+    );
+    assert_eq!(vars[1], ("m0".to_owned(), Value::from("/projects/42/%")));
+
+    let matchers = vec![
+        FieldMatcher::Exact("alice".to_owned()),
+        FieldMatcher::Prefix("/projects/".to_owned()),
+    ];
+    let (query, _) = matching_policy_query(&Layout::default(), "p", 0, &matchers, false);
+    assert_eq!(
+        query,
+        "FOR r IN @@collection\n    FILTER r.ptype == @ptype AND r.v0 == @m0 AND STARTS_WITH(r.v1, @m1)\n    REMOVE r IN @@collection\n    RETURN OLD"
+    );
+}
+
+#[tokio::test]
+async fn test_remove_matching_policy() {
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_matching")
+        .await
+        .unwrap();
+    assert!(adapter.clear_policy().await.is_ok());
+
+    let rules = vec![
+        to_owned(vec!["alice", "/projects/42/docs", "read"]),
+        to_owned(vec!["bob", "/projects/42/src", "write"]),
+        to_owned(vec!["bob", "/projects/420", "write"]),
+    ];
+    assert!(adapter.add_policies("", "p", rules.clone()).await.unwrap());
+
+    let matchers = vec![FieldMatcher::Glob("/projects/42/*".to_owned())];
+    let mut matched = adapter
+        .remove_matching_policy("p", 1, matchers.clone(), true)
+        .await
+        .unwrap();
+    matched.sort();
+    assert_eq!(matched, rules[..2].to_vec());
+
+    // the dry run left them in place
+    let mut removed = adapter
+        .remove_matching_policy("p", 1, matchers, false)
+        .await
+        .unwrap();
+    removed.sort();
+    assert_eq!(removed, matched);
+
+    let removed = adapter
+        .remove_matching_policy("p", 0, vec![FieldMatcher::Regex("^b".to_owned())], false)
+        .await
+        .unwrap();
+    assert_eq!(removed, rules[2..].to_vec());
+}
+
+#[test]
+fn test_group_policy_query() {
+    use crate::casbin_dao::group_policy_query;
+    use serde_json::{json, Value};
+
+    let p = FilterGroup::new("p")
+        .with_field(
+            1,
+            FieldMatcher::In(to_owned(vec!["domain1", "domain2"])),
+        )
+        .with_field(2, FieldMatcher::Prefix("data".to_owned()));
+    let g2 = FilterGroup::new("g2").with_field(0, FieldMatcher::Regex("^a".to_owned()));
+
+    let (query, vars) = group_policy_query(&Layout::default(), &[&p, &g2]);
+    assert_eq!(
+        query,
+        format!(
+            "FOR r IN @@collection\n    FILTER (r.ptype == @t0 AND r.v1 IN @m0_0 AND STARTS_WITH(r.v2, @m0_1)) OR (r.ptype == @t1 AND REGEX_TEST(r.v0, @m1_0))\n    FILTER {}\n    RETURN r",
+            crate::validity::validity_filter("r")
+        )
+    );
+    assert_eq!(
+        vars,
+        vec![
+            ("t0".to_owned(), Value::from("p")),
+            ("m0_0".to_owned(), json!(["domain1", "domain2"])),
+            ("m0_1".to_owned(), Value::from("data")),
+            ("t1".to_owned(), Value::from("g2")),
+            ("m1_0".to_owned(), Value::from("^a")),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_load_filtered_policy_with() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_arango_filter")
+        .await
+        .unwrap();
+
+    let mut e = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let filter = ArangoFilter::new()
+        .with_group(
+            FilterGroup::new("p")
+                .with_field(1, FieldMatcher::Exact("domain1".to_owned()))
+                .with_field(3, FieldMatcher::In(to_owned(vec!["read"]))),
+        )
+        .with_group(
+            FilterGroup::new("g").with_field(2, FieldMatcher::Prefix("domain1".to_owned())),
+        );
+
+    let mut m = DefaultModel::from_file("examples/rbac_with_domains_model.conf")
+        .await
+        .unwrap();
+    adapter.load_filtered_policy_with(&mut m, &filter).await.unwrap();
+    assert!(adapter.is_filtered());
+
+    let mut e = Enforcer::new_raw(m, adapter).await.unwrap();
+    e.build_role_links().unwrap();
+    assert_eq!(
+        e.get_policy(),
+        vec![to_owned(vec!["admin", "domain1", "data1", "read"])]
+    );
+    assert!(e.enforce(("alice", "domain1", "data1", "read")).unwrap());
+    assert!(!e.enforce(("alice", "domain1", "data1", "write")).unwrap());
+    assert!(!e.enforce(("bob", "domain2", "data2", "read")).unwrap());
+}
+
+#[test]
+fn test_exact_filter_group() {
+    assert_eq!(
+        FilterGroup::exact("g", &to_owned(vec!["", "", "domain1"])),
+        FilterGroup::new("g").with_field(2, FieldMatcher::Exact("domain1".to_owned()))
+    );
+    assert_eq!(FilterGroup::exact("p", &[]), FilterGroup::new("p"));
+}
+
+#[tokio::test]
+async fn test_load_incremental_filtered_policy() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_incremental")
+        .await
+        .unwrap();
+
+    let mut e = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let domain = |domain: &str| {
+        ArangoFilter::new()
+            .with_group(FilterGroup::new("p").with_field(1, FieldMatcher::Exact(domain.to_owned())))
+            .with_group(FilterGroup::new("g").with_field(2, FieldMatcher::Exact(domain.to_owned())))
+    };
+
+    let mut m = DefaultModel::from_file("examples/rbac_with_domains_model.conf")
+        .await
+        .unwrap();
+    assert!(!adapter.is_filtered());
+    assert!(adapter
+        .load_incremental_filtered_policy(&mut m, &domain("domain1"))
+        .await
+        .unwrap());
+    assert!(adapter.is_filtered());
+
+    let handle = adapter.filter_handle();
+    let mut e = Enforcer::new_raw(m, adapter).await.unwrap();
+    e.build_role_links().unwrap();
+    assert!(e.enforce(("alice", "domain1", "data1", "read")).unwrap());
+    assert!(!e.enforce(("bob", "domain2", "data2", "read")).unwrap());
+
+    // the enforcer's own adapter records the filters loaded through the handle
+    assert!(handle
+        .load_incremental_filtered_policy(&mut e, &domain("domain2"))
+        .await
+        .unwrap());
+    assert!(!handle
+        .load_incremental_filtered_policy(&mut e, &domain("domain1"))
+        .await
+        .unwrap());
+    assert_eq!(
+        handle.loaded_filters(),
+        vec![domain("domain1"), domain("domain2")]
+    );
+    assert!(e.enforce(("alice", "domain1", "data1", "read")).unwrap());
+    assert!(e.enforce(("bob", "domain2", "data2", "read")).unwrap());
+    assert!(e.is_filtered());
+
+    // loading the whole policy makes the model complete again
+    e.load_policy().await.unwrap();
+    assert!(!e.is_filtered());
+    assert!(handle.loaded_filters().is_empty());
+    assert!(!handle
+        .load_incremental_filtered_policy(&mut e, &domain("domain1"))
+        .await
+        .unwrap());
+}
+
+#[test]
+fn test_ptype_section() {
+    use crate::lazy::section;
+
+    assert_eq!(section("g2").unwrap(), "g");
+    // the first character, not the first byte
+    assert_eq!(section("é1").unwrap(), "é");
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&section("").unwrap_err()),
+        Some(ArangorsAdapterError::InvalidPtype { ptype }) if ptype.is_empty()
+    ));
+}
+
+#[tokio::test]
+async fn test_lazy_enforcer() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db, "casbin_lazy")
+        .await
+        .unwrap();
+
+    let mut e = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let m = DefaultModel::from_file("examples/rbac_with_domains_model.conf")
+        .await
+        .unwrap();
+    let mut lazy = LazyEnforcer::new(m, adapter, SliceScope::Domain)
+        .await
+        .unwrap()
+        .with_capacity(1);
+    assert!(lazy.enforcer().is_filtered());
+    assert!(lazy.enforcer().get_policy().is_empty());
+
+    assert!(lazy
+        .enforce("domain1", ("alice", "domain1", "data1", "read"))
+        .await
+        .unwrap());
+    assert_eq!(lazy.loaded_slices(), vec!["domain1".to_owned()]);
+    assert!(lazy
+        .enforcer()
+        .get_policy()
+        .iter()
+        .all(|rule| rule[1] == "domain1"));
+
+    // domain1 is evicted
+    assert!(lazy
+        .enforce("domain2", ("bob", "domain2", "data2", "read"))
+        .await
+        .unwrap());
+    assert_eq!(lazy.loaded_slices(), vec!["domain2".to_owned()]);
+    assert!(!lazy
+        .enforcer()
+        .enforce(("alice", "domain1", "data1", "read"))
+        .unwrap());
+
+    lazy.enforcer_mut()
+        .remove_policy(to_owned(vec!["admin", "domain2", "data2", "read"]))
+        .await
+        .unwrap();
+    lazy.invalidator().invalidate("domain2");
+    assert!(!lazy
+        .enforce("domain2", ("bob", "domain2", "data2", "read"))
+        .await
+        .unwrap());
+    assert!(lazy
+        .enforce("domain2", ("bob", "domain2", "data2", "write"))
+        .await
+        .unwrap());
+
+    // the wrapper's own writes invalidate the slice
+    let rule = to_owned(vec!["admin", "domain2", "data3", "read"]);
+    assert!(lazy.add_policy("p", rule.clone()).await.unwrap());
+    assert!(lazy.loaded_slices().is_empty());
+    assert!(lazy
+        .enforce("domain2", ("bob", "domain2", "data3", "read"))
+        .await
+        .unwrap());
+    assert!(lazy.remove_policy("p", rule).await.unwrap());
+    assert!(!lazy
+        .enforce("domain2", ("bob", "domain2", "data3", "read"))
+        .await
+        .unwrap());
+}
+
+#[test]
+fn test_audit_operation_names() {
+    use serde_json::Value;
+
+    assert_eq!(
+        serde_json::to_value(AuditOperation::RemoveFilteredPolicy).unwrap(),
+        Value::from("remove_filtered_policy")
+    );
+    assert_eq!(
+        serde_json::from_value::<AuditOperation>(Value::from("save_policy")).unwrap(),
+        AuditOperation::SavePolicy
+    );
+}
+
+#[tokio::test]
+async fn test_audit() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_audited_log").await;
+    let adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_audited")
+        .with_audit("casbin_audited_log");
+    adapter.ensure_schema().await.unwrap();
+    let audit = adapter.audit_handle();
+    let tx = adapter.transaction_handle();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    e.clear_policy().await.unwrap();
+
+    audit.set_actor("carol", Some("ticket 42"));
+    e.add_policy(to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap();
+    // the transaction of the change and its entry isn't the caller's
+    assert!(!tx.is_active());
+    e.add_grouping_policy(to_owned(vec!["bob", "data2_admin"]))
+        .await
+        .unwrap();
+    audit.clear_actor();
+    e.remove_filtered_policy(1, to_owned(vec!["data2"]))
+        .await
+        .unwrap();
+
+    let adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_audited")
+        .with_audit("casbin_audited_log");
+
+    let history = adapter
+        .audit_history("p", &to_owned(vec!["bob", "data2", "write"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|entry| entry.operation)
+            .collect::<Vec<_>>(),
+        vec![
+            AuditOperation::AddPolicy,
+            AuditOperation::RemoveFilteredPolicy
+        ]
+    );
+    assert_eq!(history[0].actor.as_deref(), Some("carol"));
+    assert_eq!(history[0].reason.as_deref(), Some("ticket 42"));
+    assert_eq!(history[1].actor, None);
+
+    let history = adapter.audit_history_for_subject("bob").await.unwrap();
+    assert_eq!(history.len(), 3);
+    assert!(history
+        .iter()
+        .any(|entry| entry.ptype.as_deref() == Some("g")
+            && entry.rule == to_owned(vec!["bob", "data2_admin"])));
+
+    // rules already stored aren't attributed to whoever adds them again
+    let mut idempotent = ArangorsAdapter::new_with_collection(db.clone(), "casbin_audited")
+        .with_audit("casbin_audited_log")
+        .with_idempotent_add(true);
+    idempotent.audit_handle().set_actor("dave", None);
+    assert!(idempotent
+        .add_policies(
+            "g",
+            "g",
+            vec![
+                to_owned(vec!["bob", "data2_admin"]),
+                to_owned(vec!["eve", "data2_admin"]),
+            ],
+        )
+        .await
+        .unwrap());
+    let history = adapter
+        .audit_history("g", &to_owned(vec!["bob", "data2_admin"]))
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].actor.as_deref(), Some("carol"));
+    let history = adapter.audit_history_for_subject("eve").await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].actor.as_deref(), Some("dave"));
+
+    let disabled = ArangorsAdapter::new_with_collection(db, "casbin_audited");
+    let e = disabled.audit_history_for_subject("bob").await.unwrap_err();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&e),
+        Some(ArangorsAdapterError::AuditNotEnabled)
+    ));
+}
+
+#[tokio::test]
+async fn test_versions() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let _ = db.drop_collection("casbin_versioned_versions").await;
+    let _ = db.drop_collection("casbin_versioned_versions_rules").await;
+    let mut adapter = ArangorsAdapter::new_with_setup(db.clone(), "casbin_versioned")
+        .await
+        .unwrap()
+        .with_versions("casbin_versioned_versions");
+    adapter.ensure_schema().await.unwrap();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+    let before = adapter.snapshot("before cleanup").await.unwrap();
+    assert_eq!(before.label, "before cleanup");
+    assert_eq!(before.rules, e.get_policy().len() + e.get_grouping_policy().len());
+
+    assert!(adapter
+        .remove_policy("", "p", to_owned(vec!["alice", "data1", "read"]))
+        .await
+        .unwrap());
+    assert!(adapter
+        .add_policy("", "p", to_owned(vec!["eve", "data3", "read"]))
+        .await
+        .unwrap());
+    let after = adapter.snapshot("after cleanup").await.unwrap();
+
+    assert_eq!(
+        adapter.list_versions().await.unwrap(),
+        vec![before.clone(), after.clone()]
+    );
+    assert_eq!(
+        adapter.diff_versions(&before.key, &after.key).await.unwrap(),
+        PolicyDiff {
+            added: vec![("p".to_owned(), to_owned(vec!["eve", "data3", "read"]))],
+            removed: vec![("p".to_owned(), to_owned(vec!["alice", "data1", "read"]))],
+        }
+    );
+
+    adapter.rollback(&before.key).await.unwrap();
+    let e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("alice", "data1", "read")).unwrap());
+    assert!(!e.enforce(("eve", "data3", "read")).unwrap());
+
+    let adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_versioned")
+        .with_versions("casbin_versioned_versions");
+    let e = adapter.rollback("missing").await.unwrap_err();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&e),
+        Some(ArangorsAdapterError::VersionNotFound { .. })
+    ));
+
+    // versions created within the same millisecond keep their order, though
+    // "10" sorts before "9" as a string
+    let tied: Vec<PolicyVersion> = db
+        .aql_str(
+            r#"FOR key IN ["9", "10"]
+    INSERT {_key: key, label: key, created_at: "2000-01-01T00:00:00.000Z", rules: 0}
+        INTO casbin_versioned_versions
+    RETURN UNSET(NEW, "_id", "_rev")"#,
+        )
+        .await
+        .unwrap();
+    assert_eq!(adapter.list_versions().await.unwrap()[..2], tied[..]);
+}
+
+#[test]
+fn test_validity_bounds() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let from = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+    let until = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+
+    assert_eq!(Validity::default().bounds(), (None, None));
+    assert_eq!(Validity::until(until).bounds(), (None, Some(1_800_000_000)));
+    assert_eq!(
+        Validity::between(from, until).bounds(),
+        (Some(1_700_000_000), Some(1_800_000_000))
+    );
+    assert_eq!(
+        validity::unix_seconds(UNIX_EPOCH - Duration::from_secs(1)),
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_validity() {
+    use casbin::prelude::*;
+    use std::time::{Duration, SystemTime};
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter =
+        ArangorsAdapter::new_with_collection(db, "casbin_windowed").with_ttl_index(true);
+    adapter.ensure_schema().await.unwrap();
+    adapter.clear_policy().await.unwrap();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+
+    let now = SystemTime::now();
+    let hour = Duration::from_secs(3600);
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data1", "read"]),
+            Validity::between(now - hour, now + hour),
+        )
+        .await
+        .unwrap());
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data2", "read"]),
+            Validity::until(now - hour),
+        )
+        .await
+        .unwrap());
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data2", "write"]),
+            Validity::between(now + hour, now + 2 * hour),
+        )
+        .await
+        .unwrap());
+    for rule in [vec!["eve", "data3", "read"], vec!["eve", "data3", "write"]] {
+        assert!(adapter
+            .add_policy_with_validity("p", to_owned(rule), Validity::until(now - hour))
+            .await
+            .unwrap());
+    }
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("eve", "data1", "read")).unwrap());
+    assert!(!e.enforce(("eve", "data2", "read")).unwrap());
+    assert!(!e.enforce(("eve", "data2", "write")).unwrap());
+    assert!(e.enforce(("alice", "data1", "read")).unwrap());
+
+    // saving keeps the windows, the pending rule included
+    e.save_policy().await.unwrap();
+    e.load_policy().await.unwrap();
+    assert!(e.enforce(("eve", "data1", "read")).unwrap());
+    assert!(!e.enforce(("eve", "data2", "write")).unwrap());
+
+    // the pending rule isn't in the model, it is revoked from the adapter
+    let pending = to_owned(vec!["eve", "data2", "write"]);
+    let revoker =
+        ArangorsAdapter::new_with_collection(conn.db("_system").await.unwrap(), "casbin_windowed");
+    assert!(revoker.revoke_policy("p", pending.clone()).await.unwrap());
+    e.save_policy().await.unwrap();
+    assert!(!revoker.revoke_policy("p", pending).await.unwrap());
+
+    // an expired rule can be granted again
+    let adapter =
+        ArangorsAdapter::new_with_collection(conn.db("_system").await.unwrap(), "casbin_windowed");
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data2", "read"]),
+            Validity::until(now + hour),
+        )
+        .await
+        .unwrap());
+    let mut e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("eve", "data2", "read")).unwrap());
+
+    // and added permanently, idempotent or not
+    assert!(e
+        .add_policy(to_owned(vec!["eve", "data3", "read"]))
+        .await
+        .unwrap());
+    assert!(e.enforce(("eve", "data3", "read")).unwrap());
+    let mut idempotent =
+        ArangorsAdapter::new_with_collection(conn.db("_system").await.unwrap(), "casbin_windowed")
+            .with_idempotent_add(true);
+    assert!(idempotent
+        .add_policy("", "p", to_owned(vec!["eve", "data3", "write"]))
+        .await
+        .unwrap());
+    let e = Enforcer::new("examples/rbac_model.conf", idempotent)
+        .await
+        .unwrap();
+    assert!(e.enforce(("eve", "data3", "write")).unwrap());
+}
+
+#[tokio::test]
+async fn test_versions_keep_windows() {
+    use casbin::prelude::*;
+    use std::time::{Duration, SystemTime};
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_collection(db, "casbin_windowed_versioned")
+        .with_versions("casbin_windowed_versions");
+    adapter.ensure_schema().await.unwrap();
+    adapter.clear_policy().await.unwrap();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+    let stored = e.get_policy().len() + e.get_grouping_policy().len();
+
+    let now = SystemTime::now();
+    let hour = Duration::from_secs(3600);
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data1", "read"]),
+            Validity::between(now - hour, now + hour),
+        )
+        .await
+        .unwrap());
+    assert!(adapter
+        .add_policy_with_validity(
+            "p",
+            to_owned(vec!["eve", "data2", "write"]),
+            Validity::between(now + hour, now + 2 * hour),
+        )
+        .await
+        .unwrap());
+
+    // the pending rule is part of the version
+    let version = adapter.snapshot("temporary access").await.unwrap();
+    assert_eq!(version.rules, stored + 2);
+
+    adapter.clear_policy().await.unwrap();
+    adapter.rollback(&version.key).await.unwrap();
+    assert_eq!(
+        adapter.snapshot("rolled back").await.unwrap().rules,
+        stored + 2
+    );
+
+    // the windows are restored, the rules didn't become permanent
+    let e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("eve", "data1", "read")).unwrap());
+    assert!(!e.enforce(("eve", "data2", "write")).unwrap());
+}
+
+#[tokio::test]
+async fn test_deterministic_keys() {
+    use arangors::Document;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_setup(db.clone(), "casbin_keyed")
+        .await
+        .unwrap()
+        .with_deterministic_keys(true);
+    assert!(adapter.clear_policy().await.is_ok());
+
+    let rule = to_owned(vec!["alice", "data1", "read"]);
+    assert!(adapter.add_policy("", "p", rule.clone()).await.unwrap());
+
+    let err = adapter
+        .add_policy("", "p", rule.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        ArangorsAdapterError::from_casbin(&err),
+        Some(ArangorsAdapterError::DuplicateRule { .. })
+    ));
+
+    let id = adapter.rule_id("p", &rule).unwrap();
+    let (_, key) = id.split_once('/').unwrap();
+    let collection = db.collection("casbin_keyed").await.unwrap();
+    let doc: Document<serde_json::Value> = collection.document(key).await.unwrap();
+    assert_eq!(doc.document["v0"], "alice");
+
+    // keys are generated by ArangoDB otherwise
+    let generated = ArangorsAdapter::new_with_collection(db.clone(), "casbin_keyed");
+    assert!(generated.rule_id("p", &rule).is_none());
+    // grouping rules are edges of the role graph
+    let graph = generated
+        .with_deterministic_keys(true)
+        .with_role_graph("casbin_keyed_roles");
+    let id = graph.rule_id("g", &to_owned(vec!["alice", "admin"])).unwrap();
+    assert!(id.starts_with("casbin_keyed_roles_edges/"));
+
+    assert!(adapter.remove_policy("", "p", rule.clone()).await.unwrap());
+    assert!(!adapter.remove_policy("", "p", rule).await.unwrap());
+}
+
+#[test]
+fn test_vertex_key() {
+    assert_eq!(graph::vertex_key("alice"), "alice");
+    assert_eq!(graph::vertex_key("data2_admin"), "data2_admin");
+    assert_eq!(graph::vertex_key("role/admin"), "role%2Fadmin");
+    assert_eq!(graph::vertex_key("a b"), "a%20b");
+    assert_eq!(graph::vertex_key("").len(), 64);
+    assert_eq!(graph::vertex_key(&"x".repeat(300)).len(), 64);
+}
+
+#[test]
+fn test_orphan_removal_query() {
+    use crate::casbin_dao::orphan_removal_query;
+
+    let graph = graph::RoleGraph::new("roles");
+    let edges = vec![
+        Layout::default()
+            .rule("g", &to_owned(vec!["alice", "admin", "domain1"]))
+            .unwrap(),
+        Layout::default()
+            .rule("g", &to_owned(vec!["bob", "admin"]))
+            .unwrap(),
+    ];
+    let names: Vec<String> = graph
+        .vertices_of(&edges)
+        .into_iter()
+        .map(|v| v.name)
+        .collect();
+    assert_eq!(names, to_owned(vec!["admin", "alice", "bob", "domain1"]));
+
+    assert_eq!(
+        orphan_removal_query(&Layout::default()),
+        r#"FOR v IN @@vertices
+    FILTER v._key IN @keys
+    LET linked = (
+        FOR e IN @@edges
+            FILTER e._from == v._id OR e._to == v._id OR e.v2 == v.name
+            LIMIT 1
+            RETURN 1
+    )
+    FILTER LENGTH(linked) == 0
+    REMOVE v IN @@vertices"#
+    );
+}
+
+#[tokio::test]
+async fn test_role_graph() {
+    use casbin::prelude::*;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_graph")
+        .with_role_graph("casbin_roles");
+    adapter.ensure_schema().await.unwrap();
+
+    let mut e = Enforcer::new("examples/rbac_model.conf", "examples/rbac_policy.csv")
+        .await
+        .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+    assert!(adapter
+        .add_policy("", "g", to_owned(vec!["bob", "data2_admin"]))
+        .await
+        .unwrap());
+
+    // the role hierarchy can be traversed with AQL
+    let aql = arangors::AqlQuery::builder()
+        .query(
+            r#"FOR v IN 1..5 OUTBOUND @start GRAPH @graph
+    RETURN v.name"#,
+        )
+        .bind_var("start", "casbin_roles_vertices/bob")
+        .bind_var("graph", "casbin_roles")
+        .build();
+    let roles: Vec<String> = db.aql_query(aql).await.unwrap();
+    assert_eq!(roles, vec!["data2_admin"]);
+
+    // domains are vertices too, and vertices left without edges are removed
+    let vertices = db.collection("casbin_roles_vertices").await.unwrap();
+    let domain = to_owned(vec!["carol", "data2_admin", "domain9"]);
+    assert!(adapter.add_policy("", "g", domain.clone()).await.unwrap());
+    assert!(vertices
+        .document::<serde_json::Value>("domain9")
+        .await
+        .is_ok());
+    assert!(adapter.remove_policy("", "g", domain).await.unwrap());
+    assert!(adapter
+        .remove_policy("", "g", to_owned(vec!["bob", "data2_admin"]))
+        .await
+        .unwrap());
+    for orphan in ["carol", "domain9", "bob"] {
+        assert!(vertices
+            .document::<serde_json::Value>(orphan)
+            .await
+            .is_err());
+    }
+    // still linked to alice
+    assert!(vertices
+        .document::<serde_json::Value>("data2_admin")
+        .await
+        .is_ok());
+    assert!(adapter
+        .add_policy("", "g", to_owned(vec!["bob", "data2_admin"]))
+        .await
+        .unwrap());
+
+    let e = Enforcer::new("examples/rbac_model.conf", adapter)
+        .await
+        .unwrap();
+    assert!(e.enforce(("alice", "data2", "read")).unwrap());
+    assert!(e.enforce(("bob", "data2", "write")).unwrap());
+    assert!(!e.enforce(("bob", "data1", "read")).unwrap());
+}
+
+#[cfg(feature = "role-manager")]
+#[tokio::test(flavor = "multi_thread")]
+async fn test_role_manager() {
+    use casbin::prelude::*;
+    use casbin::RoleManager;
+    use std::time::{Duration, SystemTime};
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let mut adapter = ArangorsAdapter::new_with_collection(db.clone(), "casbin_rm")
+        .with_role_graph("casbin_rm_roles");
+    adapter.ensure_schema().await.unwrap();
+
+    let mut e = Enforcer::new(
+        "examples/rbac_with_domains_model.conf",
+        "examples/rbac_with_domains_policy.csv",
+    )
+    .await
+    .unwrap();
+    assert!(adapter.save_policy(e.get_mut_model()).await.is_ok());
+    assert!(adapter
+        .add_policy("", "g", to_owned(vec!["carol", "alice", "domain1"]))
+        .await
+        .unwrap());
+    let expired = Validity::until(SystemTime::now() - Duration::from_secs(3600));
+    assert!(adapter
+        .add_policy_with_validity("g", to_owned(vec!["dave", "admin", "domain1"]), expired)
+        .await
+        .unwrap());
+
+    let mut rm = ArangorsRoleManager::new(db, "casbin_rm_roles").with_cache(16);
+    assert!(rm.has_link("alice", "admin", Some("domain1")));
+    assert!(rm.has_link("carol", "admin", Some("domain1")));
+    assert!(!rm.has_link("dave", "admin", Some("domain1")));
+    assert!(rm.get_users("admin", Some("domain1")).iter().all(|u| u != "dave"));
+    assert!(!rm.has_link("alice", "admin", Some("domain2")));
+    assert!(!rm.has_link("admin", "alice", Some("domain1")));
+    assert_eq!(rm.get_roles("carol", Some("domain1")), vec!["alice"]);
+    assert_eq!(rm.get_users("admin", Some("domain2")), vec!["bob"]);
+
+    let mut shallow =
+        ArangorsRoleManager::new(conn.db("_system").await.unwrap(), "casbin_rm_roles")
+            .with_max_depth(1);
+    assert!(!shallow.has_link("carol", "admin", Some("domain1")));
+    assert_eq!(shallow.failed_queries(), 0);
+
+    // failures are counted and not cached
+    let mut missing =
+        ArangorsRoleManager::new(conn.db("_system").await.unwrap(), "casbin_rm_missing")
+            .with_cache(16);
+    assert!(!missing.has_link("alice", "admin", Some("domain1")));
+    assert!(!missing.has_link("alice", "admin", Some("domain1")));
+    assert_eq!(missing.failed_queries(), 2);
+
+    let mut e = Enforcer::new("examples/rbac_with_domains_model.conf", adapter)
+        .await
+        .unwrap();
+    e.enable_auto_build_role_links(false);
+    e.set_role_manager(Arc::new(parking_lot::RwLock::new(rm)))
+        .unwrap();
+    assert!(e.enforce(("carol", "domain1", "data1", "read")).unwrap());
+    assert!(!e.enforce(("carol", "domain2", "data2", "read")).unwrap());
+}
+
+#[cfg(feature = "watcher")]
+#[tokio::test]
+async fn test_watcher_notifies_other_instances() {
+    use casbin::{EventData, Watcher};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let conn = arangors::Connection::establish_jwt("http://localhost:8529", "root", "root")
+        .await
+        .unwrap();
+
+    let db = conn.db("_system").await.unwrap();
+    let interval = Duration::from_millis(50);
+
+    let mut watcher_a = ArangorsWatcher::new(db.clone(), "casbin_watcher", interval)
+        .await
+        .unwrap();
+    let mut watcher_b = ArangorsWatcher::new(db, "casbin_watcher", interval)
+        .await
+        .unwrap();
+
+    let calls_a = Arc::new(AtomicUsize::new(0));
+    let calls_b = Arc::new(AtomicUsize::new(0));
+    let (a, b) = (calls_a.clone(), calls_b.clone());
+    watcher_a.set_update_callback(Box::new(move || {
+        a.fetch_add(1, Ordering::SeqCst);
+    }));
+    watcher_b.set_update_callback(Box::new(move || {
+        b.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    watcher_a.update(EventData::ClearPolicy);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(calls_a.load(Ordering::SeqCst), 0);
+    assert_eq!(calls_b.load(Ordering::SeqCst), 1);
+}
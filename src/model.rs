@@ -1,14 +1,45 @@
-use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone)]
 pub(crate) struct CasbinRule {
-    #[serde(skip_serializing_if = "Option::is_none")]
     pub _key: Option<String>,
+    pub _from: Option<String>,
+    pub _to: Option<String>,
     pub ptype: String,
-    pub v0: String,
-    pub v1: String,
-    pub v2: String,
-    pub v3: String,
-    pub v4: String,
-    pub v5: String,
+    /// The values of the rule, in order, as stored by the layout.
+    pub values: Vec<String>,
+    /// Unix time in seconds from which the rule applies, if bounded.
+    pub valid_from: Option<u64>,
+    /// Unix time in seconds until which the rule applies, if bounded.
+    pub valid_until: Option<u64>,
+}
+
+impl CasbinRule {
+    /// Document key derived from the rule content: the hex encoded SHA-256 of
+    /// the length-prefixed ptype and values, padded to six with `""`. A rule
+    /// ending with an empty value, which is only kept when absent values are
+    /// stored as `null`, hashes its length as well.
+    pub fn content_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        let padding = 6usize.saturating_sub(self.values.len());
+
+        for value in std::iter::once(self.ptype.as_str())
+            .chain(self.values.iter().map(String::as_str))
+            .chain(std::iter::repeat_n("", padding))
+        {
+            hasher.update((value.len() as u64).to_be_bytes());
+            hasher.update(value.as_bytes());
+        }
+
+        if self.values.last().is_some_and(String::is_empty) {
+            hasher.update((self.values.len() as u64).to_be_bytes());
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// The value at `index`, `""` if the rule is shorter.
+    pub fn value(&self, index: usize) -> &str {
+        self.values.get(index).map_or("", String::as_str)
+    }
 }
@@ -0,0 +1,271 @@
+use crate::casbin_dao::AqlExecutor;
+use crate::graph::RoleGraph;
+use crate::layout::Layout;
+use crate::validity::validity_filter;
+use crate::DocumentLayout;
+use arangors::uclient::ClientExt;
+use arangors::{AqlQuery, ClientError, Database};
+use casbin::Model;
+use casbin::{MatchingFn, Result, RoleManager};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use tokio::runtime::Handle;
+
+const DEFAULT_PTYPE: &str = "g";
+const DEFAULT_MAX_DEPTH: usize = 10;
+
+/// A casbin [`RoleManager`] answering from the role graph stored by an
+/// adapter configured with `with_role_graph`, instead of rebuilding the role
+/// hierarchy in memory.
+///
+/// `has_link` follows up to `max_depth` grouping rules with an AQL traversal,
+/// `get_roles` and `get_users` return the direct roles and users like casbin's
+/// default role manager. Grouping rules outside their validity window are not
+/// followed. Links are only read from the database: `add_link`,
+/// `delete_link` and `clear` just invalidate the cache, so the enforcer's
+/// automatic role link building can be disabled. Role and domain matching
+/// functions are not supported.
+///
+/// The trait is synchronous, queries block the calling worker thread and
+/// require a multi-threaded tokio runtime. A failed query answers as if there
+/// were no link: it is logged, left out of the cache and counted by
+/// [`failed_queries`](Self::failed_queries).
+pub struct ArangorsRoleManager<C: ClientExt> {
+    database: Database<C>,
+    graph: RoleGraph,
+    ptype: String,
+    layout: Layout,
+    max_depth: usize,
+    runtime: Handle,
+    cache: Option<Mutex<RoleCache>>,
+    failures: AtomicU64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Lookup {
+    Roles,
+    ImplicitRoles,
+    Users,
+}
+
+type CacheKey = (Lookup, String, String);
+
+/// The most recent answers, evicted in insertion order.
+struct RoleCache {
+    capacity: usize,
+    entries: HashMap<CacheKey, Vec<String>>,
+    order: VecDeque<CacheKey>,
+}
+
+impl RoleCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<String>> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: CacheKey, names: Vec<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.insert(key.clone(), names).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+impl<C: ClientExt + Send + 'static> ArangorsRoleManager<C> {
+    /// Reads the `g` rules of the role graph `graph`. Must be called from
+    /// within a tokio runtime.
+    pub fn new(database: Database<C>, graph: &str) -> Self {
+        Self {
+            database,
+            graph: RoleGraph::new(graph),
+            ptype: DEFAULT_PTYPE.to_owned(),
+            layout: Layout::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            runtime: Handle::current(),
+            cache: None,
+            failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads the rules of another grouping ptype, such as `g2`.
+    pub fn with_ptype(mut self, ptype: &str) -> Self {
+        self.ptype = ptype.to_owned();
+        self
+    }
+
+    /// The layout the adapter stores the rules with.
+    pub fn with_layout(mut self, layout: DocumentLayout) -> Self {
+        self.layout = layout.into();
+        self
+    }
+
+    /// Reads rules stored with the field names of the model, see
+    /// `ArangorsAdapter::with_named_fields`.
+    pub fn with_named_fields(mut self, m: &dyn Model) -> Self {
+        self.layout = Layout::named(m);
+        self
+    }
+
+    /// How many grouping rules `has_link` follows, 10 by default.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Keeps the last `capacity` answers in memory until a link changes.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = Some(Mutex::new(RoleCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }));
+        self
+    }
+
+    /// How many queries failed since the role manager was created, each
+    /// answered as if there were no link.
+    pub fn failed_queries(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn lookup(&self, lookup: Lookup, name: &str, domain: Option<&str>) -> Vec<String> {
+        let key = (
+            lookup,
+            name.to_owned(),
+            domain.unwrap_or_default().to_owned(),
+        );
+
+        if let Some(names) = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.lock().unwrap_or_else(PoisonError::into_inner).get(&key))
+        {
+            return names;
+        }
+
+        let query = self.traversal(lookup, name, domain);
+        let names = match tokio::task::block_in_place(|| self.runtime.block_on(query)) {
+            Ok(names) => names,
+            Err(e) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                log::error!(
+                    "cannot read the roles of `{}` from graph `{}`: {}",
+                    name,
+                    self.graph.name,
+                    e
+                );
+                return vec![];
+            }
+        };
+
+        if let Some(cache) = &self.cache {
+            cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(key, names.clone());
+        }
+
+        names
+    }
+
+    async fn traversal(
+        &self,
+        lookup: Lookup,
+        name: &str,
+        domain: Option<&str>,
+    ) -> std::result::Result<Vec<String>, ClientError> {
+        let (direction, depth) = match lookup {
+            Lookup::Roles => ("OUTBOUND", 1),
+            Lookup::ImplicitRoles => ("OUTBOUND", self.max_depth),
+            Lookup::Users => ("INBOUND", 1),
+        };
+
+        // rules without a domain store it as "" or leave it out
+        let domains = match domain {
+            Some(domain) if !domain.is_empty() => vec![Some(domain)],
+            _ => vec![Some(""), None],
+        };
+
+        let query = format!(
+            r#"FOR v, e, p IN 1..@depth {} @start GRAPH @graph
+    FILTER p.edges[*].ptype ALL == @ptype
+    FILTER p.edges[*].{} ALL IN @domains
+    FILTER LENGTH(p.edges[* FILTER NOT ({})]) == 0
+    RETURN DISTINCT v.name"#,
+            direction,
+            self.layout.field(&self.ptype, 2),
+            validity_filter("CURRENT")
+        );
+
+        let aql = AqlQuery::builder()
+            .query(&query)
+            .bind_var("depth", depth)
+            .bind_var("start", self.graph.vertex_id(name))
+            .bind_var("graph", self.graph.name.as_str())
+            .bind_var("ptype", self.ptype.as_str())
+            .bind_var("domains", domains)
+            .build();
+
+        self.database.run(aql).await
+    }
+
+    fn invalidate(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+}
+
+impl<C: ClientExt + Send + 'static> RoleManager for ArangorsRoleManager<C> {
+    fn clear(&mut self) {
+        self.invalidate();
+    }
+
+    fn add_link(&mut self, _name1: &str, _name2: &str, _domain: Option<&str>) {
+        self.invalidate();
+    }
+
+    fn matching_fn(
+        &mut self,
+        _role_matching_fn: Option<MatchingFn>,
+        _domain_matching_fn: Option<MatchingFn>,
+    ) {
+    }
+
+    fn delete_link(&mut self, _name1: &str, _name2: &str, _domain: Option<&str>) -> Result<()> {
+        self.invalidate();
+        Ok(())
+    }
+
+    fn has_link(&mut self, name1: &str, name2: &str, domain: Option<&str>) -> bool {
+        if name1 == name2 {
+            return true;
+        }
+
+        self.lookup(Lookup::ImplicitRoles, name1, domain)
+            .iter()
+            .any(|role| role == name2)
+    }
+
+    fn get_roles(&mut self, name: &str, domain: Option<&str>) -> Vec<String> {
+        self.lookup(Lookup::Roles, name, domain)
+    }
+
+    fn get_users(&self, name: &str, domain: Option<&str>) -> Vec<String> {
+        self.lookup(Lookup::Users, name, domain)
+    }
+}
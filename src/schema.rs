@@ -0,0 +1,335 @@
+use crate::casbin_dao::padded_rule_query;
+use crate::error::{error_num, ERROR_ARANGO_DATA_SOURCE_NOT_FOUND, ERROR_ARANGO_DUPLICATE_NAME};
+use crate::layout::{Layout, Shape};
+use crate::versions::Versions;
+use crate::ArangorsAdapterError;
+use arangors::index::{Index, IndexSettings};
+use arangors::uclient::ClientExt;
+use arangors::{AqlQuery, Database};
+use casbin::Result;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Name of the unique index installed by [`ensure_schema`].
+pub(crate) const UNIQUE_INDEX_NAME: &str = "casbin_rule_unique";
+
+/// Name of the index the audit history is read through.
+const AUDIT_INDEX_NAME: &str = "casbin_audit_rule";
+
+/// Name of the index the rules of a version are read through.
+const VERSION_INDEX_NAME: &str = "casbin_version";
+
+/// Name of the TTL index deleting expired rules.
+const TTL_INDEX_NAME: &str = "casbin_valid_until";
+
+/// The fields covered before rules could have more than six values.
+const LEGACY_RULE_FIELDS: [&str; 7] = ["ptype", "v0", "v1", "v2", "v3", "v4", "v5"];
+
+/// Creates the policy collection if it doesn't exist yet and makes sure it
+/// carries a unique index over the rule attributes of the layout.
+pub(crate) async fn ensure_schema<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    layout: &Layout,
+) -> Result<()> {
+    ensure_collection(database, collection).await?;
+    ensure_unique_index(database, collection, layout).await
+}
+
+pub(crate) async fn ensure_collection<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+) -> Result<()> {
+    match database.collection(collection).await {
+        Ok(_) => Ok(()),
+        Err(e) if error_num(&e) == Some(ERROR_ARANGO_DATA_SOURCE_NOT_FOUND) => {
+            match database.create_collection(collection).await {
+                Ok(_) => Ok(()),
+                // another instance created it in the meantime
+                Err(e) if error_num(&e) == Some(ERROR_ARANGO_DUPLICATE_NAME) => Ok(()),
+                Err(e) => Err(ArangorsAdapterError::from(e).into()),
+            }
+        }
+        Err(e) => Err(ArangorsAdapterError::from(e).into()),
+    }
+}
+
+pub(crate) async fn ensure_unique_index<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    layout: &Layout,
+) -> Result<()> {
+    let fields = layout.index_fields();
+    let legacy = layout.shape == Shape::Columns;
+
+    let mut indexes = database
+        .indexes(collection)
+        .await
+        .map_err(ArangorsAdapterError::from)?
+        .indexes;
+
+    if let Some(index) = indexes.iter().find(|i| i.name == UNIQUE_INDEX_NAME) {
+        if is_rule_index(index, &fields) {
+            return Ok(());
+        }
+
+        // installed by an earlier version, rebuilt to cover the extra values
+        if legacy && is_rule_index(index, &LEGACY_RULE_FIELDS) {
+            database
+                .delete_index(&index.id)
+                .await
+                .map_err(ArangorsAdapterError::from)?;
+
+            return create_unique_index(database, collection, &fields).await;
+        }
+
+        return Err(ArangorsAdapterError::IndexMismatch {
+            collection: collection.to_owned(),
+            index: index.name.clone(),
+            expected: fields,
+            fields: index.fields.clone(),
+        }
+        .into());
+    }
+
+    // an equivalent index created by hand, as the README used to suggest
+    if indexes.iter().any(|i| is_rule_index(i, &fields)) {
+        return Ok(());
+    }
+
+    // created by hand before rules could have more than six values, it would
+    // reject rules differing past the sixth one
+    if legacy {
+        if let Some(position) = indexes
+            .iter()
+            .position(|i| is_rule_index(i, &LEGACY_RULE_FIELDS))
+        {
+            let index = indexes.remove(position);
+            database
+                .delete_index(&index.id)
+                .await
+                .map_err(ArangorsAdapterError::from)?;
+        }
+    }
+
+    // any other unique index over rule attributes would reject rules the
+    // adapter tells apart, e.g. one on `ptype,v0,v1` created by hand
+    if let Some(index) = indexes.iter().find(|i| covers_rule_fields(i, &fields)) {
+        return Err(ArangorsAdapterError::IndexMismatch {
+            collection: collection.to_owned(),
+            index: index.name.clone(),
+            expected: fields,
+            fields: index.fields.clone(),
+        }
+        .into());
+    }
+
+    create_unique_index(database, collection, &fields).await
+}
+
+/// Fails with [`ArangorsAdapterError::PaddedRules`] if the collection holds
+/// rules padded with `""` while the layout stores absent positions as `null`:
+/// their padding would be read as empty values.
+pub(crate) async fn ensure_no_padded_rules<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    layout: &Layout,
+) -> Result<()> {
+    let (query, vars) = match padded_rule_query(layout) {
+        Some(query) if layout.null_absent => query,
+        _ => return Ok(()),
+    };
+
+    let mut bind_vars: HashMap<&str, Value> =
+        vars.iter().map(|(k, v)| (k.as_str(), v.clone())).collect();
+    bind_vars.insert("@collection", Value::from(collection));
+
+    let aql = AqlQuery::builder()
+        .query(&query)
+        .bind_vars(bind_vars)
+        .build();
+
+    let found: Vec<Value> = database
+        .aql_query(aql)
+        .await
+        .map_err(ArangorsAdapterError::from)?;
+
+    if !found.is_empty() {
+        return Err(ArangorsAdapterError::PaddedRules {
+            collection: collection.to_owned(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Creates the audit collection if it doesn't exist yet, with an array index
+/// on the values of the rules.
+pub(crate) async fn ensure_audit_schema<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+) -> Result<()> {
+    ensure_collection(database, collection).await?;
+    ensure_index(database, collection, AUDIT_INDEX_NAME, "rule[*]").await
+}
+
+/// Creates the collections of the versions if they don't exist yet, with an
+/// index on the version of the rules.
+pub(crate) async fn ensure_versions_schema<C: ClientExt>(
+    database: &Database<C>,
+    versions: &Versions,
+) -> Result<()> {
+    ensure_collection(database, &versions.versions).await?;
+    ensure_collection(database, &versions.rules).await?;
+    ensure_index(database, &versions.rules, VERSION_INDEX_NAME, "version").await
+}
+
+/// Installs a TTL index deleting the rules once their `valid_until` has
+/// passed, unless the collection already has a TTL index.
+pub(crate) async fn ensure_ttl_index<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+) -> Result<()> {
+    let indexes = database
+        .indexes(collection)
+        .await
+        .map_err(ArangorsAdapterError::from)?
+        .indexes;
+
+    if indexes
+        .iter()
+        .any(|i| matches!(i.settings, IndexSettings::Ttl { .. }))
+    {
+        return Ok(());
+    }
+
+    // rules without `valid_until` are ignored by the index
+    let index = Index::builder()
+        .name(TTL_INDEX_NAME)
+        .fields(vec!["valid_until".to_owned()])
+        .settings(IndexSettings::Ttl { expire_after: 0 })
+        .build();
+
+    database
+        .create_index(collection, &index)
+        .await
+        .map_err(ArangorsAdapterError::from)?;
+
+    Ok(())
+}
+
+/// Creates a persistent index on the field unless one with that name exists.
+async fn ensure_index<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    name: &str,
+    field: &str,
+) -> Result<()> {
+    let indexes = database
+        .indexes(collection)
+        .await
+        .map_err(ArangorsAdapterError::from)?
+        .indexes;
+
+    if indexes.iter().any(|i| i.name == name) {
+        return Ok(());
+    }
+
+    let index = Index::builder()
+        .name(name)
+        .fields(vec![field.to_owned()])
+        .settings(IndexSettings::Persistent {
+            unique: false,
+            sparse: false,
+            deduplicate: true,
+        })
+        .build();
+
+    database
+        .create_index(collection, &index)
+        .await
+        .map_err(ArangorsAdapterError::from)?;
+
+    Ok(())
+}
+
+/// Drops the unique indexes over the rule attributes of the layout: the one
+/// installed by [`ensure_schema`], and those created by hand, on
+/// `ptype,v0..v5` included.
+pub(crate) async fn drop_unique_index<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    layout: &Layout,
+) -> Result<()> {
+    let fields = layout.index_fields();
+    let indexes = database
+        .indexes(collection)
+        .await
+        .map_err(ArangorsAdapterError::from)?
+        .indexes;
+
+    for index in indexes.iter().filter(|i| {
+        i.name == UNIQUE_INDEX_NAME
+            || is_rule_index(i, &fields)
+            || is_rule_index(i, &LEGACY_RULE_FIELDS)
+    }) {
+        database
+            .delete_index(&index.id)
+            .await
+            .map_err(ArangorsAdapterError::from)?;
+    }
+
+    Ok(())
+}
+
+async fn create_unique_index<C: ClientExt>(
+    database: &Database<C>,
+    collection: &str,
+    fields: &[String],
+) -> Result<()> {
+    let index = Index::builder()
+        .name(UNIQUE_INDEX_NAME)
+        .fields(fields.to_vec())
+        .settings(IndexSettings::Persistent {
+            unique: true,
+            sparse: false,
+            deduplicate: false,
+        })
+        .build();
+
+    database
+        .create_index(collection, &index)
+        .await
+        .map_err(ArangorsAdapterError::from)?;
+
+    Ok(())
+}
+
+fn is_rule_index<F: AsRef<str>>(index: &Index, fields: &[F]) -> bool {
+    is_unique(index)
+        && index
+            .fields
+            .iter()
+            .map(String::as_str)
+            .eq(fields.iter().map(AsRef::as_ref))
+}
+
+/// Whether the index is unique over `ptype` followed by rule attributes of
+/// the layout or of the legacy one, whichever they are.
+fn covers_rule_fields(index: &Index, fields: &[String]) -> bool {
+    is_unique(index)
+        && index.fields.first().is_some_and(|f| f == "ptype")
+        && index.fields[1..]
+            .iter()
+            .all(|f| fields.contains(f) || LEGACY_RULE_FIELDS.contains(&f.as_str()))
+}
+
+fn is_unique(index: &Index) -> bool {
+    match index.settings {
+        IndexSettings::Persistent { unique, .. }
+        | IndexSettings::Hash { unique, .. }
+        | IndexSettings::Skiplist { unique, .. } => unique,
+        _ => false,
+    }
+}
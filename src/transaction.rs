@@ -0,0 +1,98 @@
+use crate::casbin_dao::AqlExecutor;
+use crate::ArangorsAdapterError;
+use arangors::transaction::Transaction;
+use arangors::uclient::ClientExt;
+use arangors::Database;
+use casbin::Result;
+use std::sync::{Arc, PoisonError, RwLock};
+
+pub(crate) type SharedTransaction<C> = Arc<RwLock<Option<Arc<Transaction<C>>>>>;
+
+/// Groups several adapter calls into one ArangoDB stream transaction.
+///
+/// The handle shares its state with the adapter it was obtained from, so it
+/// keeps working after the adapter was moved into an `Enforcer`: every
+/// adapter call issued between [`TransactionHandle::begin`] and
+/// [`TransactionHandle::commit`] or [`TransactionHandle::abort`] runs inside
+/// the same transaction.
+#[derive(Clone)]
+pub struct TransactionHandle<C: ClientExt> {
+    database: Database<C>,
+    collections: Vec<String>,
+    current: SharedTransaction<C>,
+}
+
+impl<C: ClientExt + Send> TransactionHandle<C> {
+    pub(crate) fn new(
+        database: Database<C>,
+        collections: Vec<String>,
+        current: SharedTransaction<C>,
+    ) -> Self {
+        Self {
+            database,
+            collections,
+            current,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    pub async fn begin(&self) -> Result<()> {
+        if self.is_active() {
+            return Err(ArangorsAdapterError::TransactionAlreadyActive.into());
+        }
+
+        let tx = self
+            .database
+            .begin(self.collections.clone())
+            .await
+            .map_err(ArangorsAdapterError::from)?;
+
+        // another task may have begun a transaction in the meantime
+        let rejected = {
+            let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+            if current.is_some() {
+                tx
+            } else {
+                *current = tx.map(Arc::new);
+                None
+            }
+        };
+
+        if let Some(tx) = rejected {
+            let _ = tx.abort().await;
+            return Err(ArangorsAdapterError::TransactionAlreadyActive.into());
+        }
+
+        Ok(())
+    }
+
+    pub async fn commit(&self) -> Result<()> {
+        let tx = self.take()?;
+
+        tx.commit().await.map_err(ArangorsAdapterError::from)?;
+
+        Ok(())
+    }
+
+    pub async fn abort(&self) -> Result<()> {
+        let tx = self.take()?;
+
+        tx.abort().await.map_err(ArangorsAdapterError::from)?;
+
+        Ok(())
+    }
+
+    fn take(&self) -> Result<Arc<Transaction<C>>> {
+        self.current
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or_else(|| ArangorsAdapterError::TransactionNotActive.into())
+    }
+}
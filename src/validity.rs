@@ -0,0 +1,49 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The AQL condition keeping the rule `document` if its validity window, if
+/// it has one, includes the server's current time. Plain rules have no
+/// window and are always kept.
+pub(crate) fn validity_filter(document: &str) -> String {
+    format!(
+        "({0}.valid_from == null OR {0}.valid_from * 1000 <= DATE_NOW()) AND ({0}.valid_until == null OR {0}.valid_until * 1000 > DATE_NOW())",
+        document
+    )
+}
+
+/// When a rule applies, for `ArangorsAdapter::add_policy_with_validity`. A
+/// bound left `None` leaves the window open on that side.
+///
+/// The bounds are stored as Unix times in seconds, in the `valid_from` and
+/// `valid_until` attributes of the rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Validity {
+    pub from: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl Validity {
+    /// A window open until `until`.
+    pub fn until(until: SystemTime) -> Self {
+        Self {
+            from: None,
+            until: Some(until),
+        }
+    }
+
+    /// A window from `from` until `until`.
+    pub fn between(from: SystemTime, until: SystemTime) -> Self {
+        Self {
+            from: Some(from),
+            until: Some(until),
+        }
+    }
+
+    pub(crate) fn bounds(&self) -> (Option<u64>, Option<u64>) {
+        (self.from.map(unix_seconds), self.until.map(unix_seconds))
+    }
+}
+
+pub(crate) fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
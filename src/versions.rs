@@ -0,0 +1,168 @@
+use crate::casbin_dao::AqlExecutor;
+use crate::ArangorsAdapterError;
+use arangors::AqlQuery;
+use casbin::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Where snapshots of the policy are kept: a document per version in the
+/// collection `name`, and its rules in `{name}_rules`.
+#[derive(Clone)]
+pub(crate) struct Versions {
+    pub versions: String,
+    pub rules: String,
+}
+
+/// A snapshot of the stored policy.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyVersion {
+    /// The key of the version, to diff or roll back to it.
+    #[serde(rename = "_key")]
+    pub key: String,
+    pub label: String,
+    /// When the snapshot was taken, in ISO 8601 from the server's clock.
+    pub created_at: String,
+    /// How many rules the version holds.
+    pub rules: usize,
+}
+
+/// A rule as a version keeps it, with its validity window if it has one.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct VersionedRule {
+    pub ptype: String,
+    pub rule: Vec<String>,
+    pub valid_from: Option<u64>,
+    pub valid_until: Option<u64>,
+}
+
+/// The rules added and removed from one version to another, as
+/// `(ptype, values)` pairs.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDiff {
+    pub added: Vec<(String, Vec<String>)>,
+    pub removed: Vec<(String, Vec<String>)>,
+}
+
+impl Versions {
+    pub fn new(name: &str) -> Self {
+        Self {
+            versions: name.to_owned(),
+            rules: format!("{}_rules", name),
+        }
+    }
+
+    /// Stores the rules as a new version.
+    pub async fn create<E: AqlExecutor>(
+        &self,
+        e: &E,
+        label: &str,
+        rules: &[VersionedRule],
+    ) -> Result<PolicyVersion> {
+        let aql = AqlQuery::builder()
+            .query(
+                r#"LET version = FIRST(
+    INSERT {label: @label, created_at: DATE_ISO8601(DATE_NOW()), rules: LENGTH(@rules)}
+        INTO @@versions
+        RETURN NEW
+)
+LET stored = (
+    FOR r IN @rules
+        INSERT MERGE(r, {version: version._key}) INTO @@rules
+)
+RETURN UNSET(version, "_id", "_rev")"#,
+            )
+            .bind_var("label", label)
+            .bind_var(
+                "rules",
+                serde_json::to_value(rules).map_err(ArangorsAdapterError::from)?,
+            )
+            .bind_var("@versions", self.versions.as_str())
+            .bind_var("@rules", self.rules.as_str())
+            .build();
+
+        let versions: Vec<PolicyVersion> = e.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        versions.into_iter().next().ok_or_else(|| {
+            ArangorsAdapterError::VersionNotCreated {
+                label: label.to_owned(),
+            }
+            .into()
+        })
+    }
+
+    /// Every version, oldest first. Versions created within the same
+    /// millisecond are ordered by their keys, which the server generates in
+    /// increasing numeric order.
+    pub async fn list<E: AqlExecutor>(&self, e: &E) -> Result<Vec<PolicyVersion>> {
+        let aql = AqlQuery::builder()
+            .query(
+                r#"FOR v IN @@versions
+    SORT v.created_at, TO_NUMBER(v._key)
+    RETURN UNSET(v, "_id", "_rev")"#,
+            )
+            .bind_var("@versions", self.versions.as_str())
+            .build();
+
+        Ok(e.run(aql).await.map_err(ArangorsAdapterError::from)?)
+    }
+
+    /// The rules of the version, failing if it doesn't exist.
+    pub async fn rules<E: AqlExecutor>(&self, e: &E, version: &str) -> Result<Vec<VersionedRule>> {
+        self.check(e, &[version]).await?;
+
+        let aql = AqlQuery::builder()
+            .query(
+                r#"FOR r IN @@rules
+    FILTER r.version == @version
+    RETURN KEEP(r, "ptype", "rule", "valid_from", "valid_until")"#,
+            )
+            .bind_var("version", version)
+            .bind_var("@rules", self.rules.as_str())
+            .build();
+
+        Ok(e.run(aql).await.map_err(ArangorsAdapterError::from)?)
+    }
+
+    /// The rules added and removed from `from` to `to`, failing if either
+    /// doesn't exist.
+    pub async fn diff<E: AqlExecutor>(&self, e: &E, from: &str, to: &str) -> Result<PolicyDiff> {
+        self.check(e, &[from, to]).await?;
+
+        let aql = AqlQuery::builder()
+            .query(
+                r#"LET from = (FOR r IN @@rules FILTER r.version == @from RETURN [r.ptype, r.rule])
+LET to = (FOR r IN @@rules FILTER r.version == @to RETURN [r.ptype, r.rule])
+RETURN {added: MINUS(to, from), removed: MINUS(from, to)}"#,
+            )
+            .bind_var("from", from)
+            .bind_var("to", to)
+            .bind_var("@rules", self.rules.as_str())
+            .build();
+
+        let diffs: Vec<PolicyDiff> = e.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        Ok(diffs.into_iter().next().unwrap_or_default())
+    }
+
+    async fn check<E: AqlExecutor>(&self, e: &E, versions: &[&str]) -> Result<()> {
+        let aql = AqlQuery::builder()
+            .query(
+                r#"FOR key IN @keys
+    FILTER DOCUMENT(@versions, key) == null
+    RETURN key"#,
+            )
+            .bind_var("keys", versions.to_vec())
+            .bind_var("versions", self.versions.as_str())
+            .build();
+
+        let missing: Vec<Value> = e.run(aql).await.map_err(ArangorsAdapterError::from)?;
+
+        match missing.first().and_then(Value::as_str) {
+            Some(version) => Err(ArangorsAdapterError::VersionNotFound {
+                version: version.to_owned(),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::casbin_dao::AqlExecutor;
+use crate::error::{error_num, ERROR_ARANGO_CONFLICT, ERROR_ARANGO_UNIQUE_CONSTRAINT_VIOLATED};
+use crate::schema;
+use crate::ArangorsAdapterError;
+use arangors::uclient::ClientExt;
+use arangors::{AqlQuery, ClientError, Database};
+use casbin::{EventData, Result, Watcher};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+const REVISION_KEY: &str = "revision";
+
+/// How many times a bump losing a race against another instance is retried.
+const MAX_BUMP_ATTEMPTS: u64 = 5;
+
+type UpdateCallback = Box<dyn FnMut() + Send + Sync>;
+
+/// A casbin [`Watcher`] keeping several enforcers in sync through ArangoDB.
+///
+/// Every policy change bumps a revision document in the watcher collection.
+/// Each watcher polls that document and invokes the update callback when
+/// another instance changed the policy, typically to reload it. A bump losing
+/// a race against another instance is retried, one that still fails is
+/// logged through the `log` crate.
+pub struct ArangorsWatcher<C: ClientExt> {
+    database: Database<C>,
+    collection: String,
+    runtime: Handle,
+    state: Arc<WatcherState>,
+    poller: JoinHandle<()>,
+}
+
+struct WatcherState {
+    callback: Mutex<Option<UpdateCallback>>,
+    revisions: Mutex<Revisions>,
+}
+
+/// The last revision handled and the revisions written by this instance,
+/// which must not trigger the callback, along with the bumps still running,
+/// whose revision isn't known yet.
+struct Revisions {
+    seen: u64,
+    own: HashSet<u64>,
+    pending: usize,
+}
+
+impl<C: ClientExt + Send + 'static> ArangorsWatcher<C> {
+    /// Creates the watcher collection if needed and starts polling it every
+    /// `poll_interval`. Must be called from within a tokio runtime.
+    pub async fn new(
+        database: Database<C>,
+        collection: &str,
+        poll_interval: Duration,
+    ) -> Result<Self> {
+        schema::ensure_collection(&database, collection).await?;
+
+        let seen = read_revision(&database, collection)
+            .await
+            .map_err(ArangorsAdapterError::from)?;
+
+        let state = Arc::new(WatcherState {
+            callback: Mutex::new(None),
+            revisions: Mutex::new(Revisions {
+                seen,
+                own: HashSet::new(),
+                pending: 0,
+            }),
+        });
+
+        let runtime = Handle::current();
+        let poller = runtime.spawn(poll(
+            database.clone(),
+            collection.to_owned(),
+            poll_interval,
+            state.clone(),
+        ));
+
+        Ok(Self {
+            database,
+            collection: collection.to_owned(),
+            runtime,
+            state,
+            poller,
+        })
+    }
+}
+
+impl<C: ClientExt> Drop for ArangorsWatcher<C> {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+impl<C: ClientExt + Send + 'static> Watcher for ArangorsWatcher<C> {
+    fn set_update_callback(&mut self, cb: Box<dyn FnMut() + Send + Sync>) {
+        *self
+            .state
+            .callback
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(cb);
+    }
+
+    fn update(&mut self, d: EventData) {
+        let database = self.database.clone();
+        let collection = self.collection.clone();
+        let state = self.state.clone();
+        let event = d.to_string();
+
+        // reserved before the bump, so that the poller waits for its revision
+        // instead of taking it for another instance's
+        state
+            .revisions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pending += 1;
+
+        // the trait is synchronous, the notification is written in the background
+        self.runtime.spawn(async move {
+            let bumped = bump_revision(&database, &collection, &event).await;
+
+            {
+                let mut revisions = state
+                    .revisions
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                revisions.pending -= 1;
+                if let Ok(revision) = bumped {
+                    revisions.own.insert(revision);
+                }
+            }
+
+            if let Err(e) = bumped {
+                log::error!(
+                    "cannot notify other instances of the policy change ({}): {}",
+                    event,
+                    ArangorsAdapterError::from(e)
+                );
+            }
+        });
+    }
+}
+
+async fn poll<C: ClientExt + Send>(
+    database: Database<C>,
+    collection: String,
+    poll_interval: Duration,
+    state: Arc<WatcherState>,
+) {
+    loop {
+        tokio::time::sleep(poll_interval).await;
+
+        // a failed read is retried on the next tick
+        let current = match read_revision(&database, &collection).await {
+            Ok(current) => current,
+            Err(_) => continue,
+        };
+
+        let changed_elsewhere = {
+            let mut revisions = state
+                .revisions
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            if current <= revisions.seen || revisions.pending > 0 {
+                false
+            } else {
+                let changed = (revisions.seen + 1..=current).any(|r| !revisions.own.contains(&r));
+                revisions.seen = current;
+                revisions.own.retain(|&r| r > current);
+                changed
+            }
+        };
+
+        if changed_elsewhere {
+            if let Some(cb) = state
+                .callback
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .as_mut()
+            {
+                cb();
+            }
+        }
+    }
+}
+
+async fn read_revision<C: ClientExt + Send>(
+    database: &Database<C>,
+    collection: &str,
+) -> std::result::Result<u64, ClientError> {
+    let aql = AqlQuery::builder()
+        .query(
+            r#"FOR r IN @@collection
+    FILTER r._key == @key
+    RETURN r.revision"#,
+        )
+        .bind_var("key", REVISION_KEY)
+        .bind_var("@collection", collection)
+        .build();
+
+    let revisions: Vec<u64> = database.run(aql).await?;
+
+    Ok(revisions.into_iter().next().unwrap_or(0))
+}
+
+/// Bumps the revision, retrying when another instance bumped it at the same
+/// time.
+async fn bump_revision<C: ClientExt + Send>(
+    database: &Database<C>,
+    collection: &str,
+    event: &str,
+) -> std::result::Result<u64, ClientError> {
+    let mut attempt = 1;
+
+    loop {
+        match try_bump_revision(database, collection, event).await {
+            Err(e) if attempt < MAX_BUMP_ATTEMPTS && is_conflict(&e) => {
+                tokio::time::sleep(Duration::from_millis(10 * attempt)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn try_bump_revision<C: ClientExt + Send>(
+    database: &Database<C>,
+    collection: &str,
+    event: &str,
+) -> std::result::Result<u64, ClientError> {
+    let aql = AqlQuery::builder()
+        .query(
+            r#"UPSERT { _key: @key }
+    INSERT { _key: @key, revision: 1, event: @event, updated_at: DATE_ISO8601(DATE_NOW()) }
+    UPDATE { revision: OLD.revision + 1, event: @event, updated_at: DATE_ISO8601(DATE_NOW()) }
+    IN @@collection
+    RETURN NEW.revision"#,
+        )
+        .bind_var("key", REVISION_KEY)
+        .bind_var("event", event)
+        .bind_var("@collection", collection)
+        .build();
+
+    let revisions: Vec<u64> = database.run(aql).await?;
+
+    Ok(revisions.into_iter().next().unwrap_or(0))
+}
+
+/// A write conflict on the revision document, or two instances inserting it.
+fn is_conflict(e: &ClientError) -> bool {
+    matches!(
+        error_num(e),
+        Some(ERROR_ARANGO_CONFLICT | ERROR_ARANGO_UNIQUE_CONSTRAINT_VIOLATED)
+    )
+}